@@ -0,0 +1,192 @@
+//! Bindings to the functions the auto-refresh plugin exports, for other skyline plugins.
+//!
+//! The symbols are resolved when the plugins are loaded, so auto-refresh has to be installed
+//! next to the plugin using this crate. Check `is_compatible` before calling anything else.
+//! The C declarations are in `auto-refresh-server/include/auto_refresh.h`.
+
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::{c_char, c_void};
+
+/// The version these bindings were written against.
+pub const API_VERSION: u32 = 1;
+
+/// The raw exports.
+pub mod raw {
+    use std::os::raw::{c_char, c_void};
+
+    /// `result` is one of the `RefreshResult` values.
+    pub type Callback =
+        unsafe extern "C" fn(hash: u64, path: *const c_char, result: u32, user_data: *mut c_void);
+
+    /// Layout of `AutoRefreshFileInfo`.
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct FileInfo {
+        pub loaded: bool,
+        pub loaded_size: u64,
+        pub in_mods: bool,
+        pub pending: bool,
+        pub snapshotted: bool,
+    }
+
+    // Every function but the first returns an `AutoRefreshStatus`
+    extern "C" {
+        pub fn auto_refresh_api_version() -> u32;
+        pub fn auto_refresh_bntx(hash: u64, replace: *const u8, size: usize) -> i32;
+        pub fn auto_refresh_file(hash: u64, replace: *const u8, size: usize) -> i32;
+        pub fn auto_refresh_query(hash: u64, info: *mut FileInfo) -> i32;
+        pub fn auto_refresh_rescan() -> i32;
+        pub fn auto_refresh_register_callback(
+            callback: Option<Callback>,
+            user_data: *mut c_void,
+        ) -> i32;
+        pub fn auto_refresh_unregister_callback(
+            callback: Option<Callback>,
+            user_data: *mut c_void,
+        ) -> i32;
+    }
+}
+
+pub use raw::FileInfo;
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshResult {
+    Refreshed = 0,
+    /// Only part of the file could be refreshed, e.g. some textures of a BNTX
+    Partial = 1,
+    Skipped = 2,
+}
+
+impl RefreshResult {
+    pub fn from_raw(result: u32) -> Option<Self> {
+        match result {
+            0 => Some(RefreshResult::Refreshed),
+            1 => Some(RefreshResult::Partial),
+            2 => Some(RefreshResult::Skipped),
+            _ => None,
+        }
+    }
+}
+
+/// The failing `AutoRefreshStatus` codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// A pointer was null or a size was 0
+    InvalidArgument,
+    /// This game version isn't supported, or the BNTX hooks aren't installed
+    Unsupported,
+    /// The game doesn't have the file loaded
+    NotLoaded,
+    /// The hash isn't in the ARC
+    NotFound,
+    /// The refresh failed, the plugin's log has the reason
+    Failed,
+    AlreadyRegistered,
+    NotRegistered,
+    /// The plugin can't serve calls anymore, its log has the reason
+    Internal,
+    /// A code from a newer version of the plugin
+    Unknown(i32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidArgument => write!(f, "invalid argument"),
+            Error::Unsupported => write!(f, "unsupported"),
+            Error::NotLoaded => write!(f, "not loaded"),
+            Error::NotFound => write!(f, "not found in the arc"),
+            Error::Failed => write!(f, "failed"),
+            Error::AlreadyRegistered => write!(f, "already registered"),
+            Error::NotRegistered => write!(f, "not registered"),
+            Error::Internal => write!(f, "internal error"),
+            Error::Unknown(code) => write!(f, "unknown status {}", code),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Splits an `AutoRefreshStatus` into the refresh outcome or the error.
+fn check(status: i32) -> Result<RefreshResult, Error> {
+    match status {
+        0 => Ok(RefreshResult::Refreshed),
+        1 => Ok(RefreshResult::Partial),
+        -1 => Err(Error::InvalidArgument),
+        -2 => Err(Error::Unsupported),
+        -3 => Err(Error::NotLoaded),
+        -4 => Err(Error::NotFound),
+        -5 => Err(Error::Failed),
+        -6 => Err(Error::AlreadyRegistered),
+        -7 => Err(Error::NotRegistered),
+        -8 => Err(Error::Internal),
+        code => Err(Error::Unknown(code)),
+    }
+}
+
+/// Called with the file's hash, its ARC path (or the hash in hex for files pushed by hash) and
+/// the result, on auto-refresh's event thread.
+pub type Callback = fn(hash: u64, path: &str, result: RefreshResult);
+
+pub fn api_version() -> u32 {
+    unsafe { raw::auto_refresh_api_version() }
+}
+
+/// Whether the installed plugin implements the functions the way these bindings expect.
+pub fn is_compatible() -> bool {
+    api_version() == API_VERSION
+}
+
+/// Overwrites the loaded textures of a BNTX with `data`. `Partial` if some were rejected.
+pub fn refresh_bntx(hash: u64, data: &[u8]) -> Result<RefreshResult, Error> {
+    check(unsafe { raw::auto_refresh_bntx(hash, data.as_ptr(), data.len()) })
+}
+
+/// Overwrites the loaded file with `data`, which has to be the same size.
+pub fn refresh_file(hash: u64, data: &[u8]) -> Result<(), Error> {
+    check(unsafe { raw::auto_refresh_file(hash, data.as_ptr(), data.len()) }).map(|_| ())
+}
+
+pub fn query(hash: u64) -> Result<FileInfo, Error> {
+    let mut info = FileInfo::default();
+    check(unsafe { raw::auto_refresh_query(hash, &mut info) }).map(|_| info)
+}
+
+/// Rebuilds the plugin's index of the mod folders. Blocks while the SD card is scanned.
+pub fn rescan() -> Result<(), Error> {
+    check(unsafe { raw::auto_refresh_rescan() }).map(|_| ())
+}
+
+unsafe extern "C" fn call_back(
+    hash: u64,
+    path: *const c_char,
+    result: u32,
+    user_data: *mut c_void,
+) {
+    let callback: Callback = std::mem::transmute(user_data);
+    let result = match RefreshResult::from_raw(result) {
+        Some(result) => result,
+        None => return,
+    };
+    let path = if path.is_null() {
+        ""
+    } else {
+        CStr::from_ptr(path).to_str().unwrap_or("")
+    };
+    callback(hash, path, result);
+}
+
+/// Calls `callback` after every refresh.
+pub fn on_refresh(callback: Callback) -> Result<(), Error> {
+    check(unsafe { raw::auto_refresh_register_callback(Some(call_back), callback as *mut c_void) })
+        .map(|_| ())
+}
+
+pub fn remove_on_refresh(callback: Callback) -> Result<(), Error> {
+    check(unsafe {
+        raw::auto_refresh_unregister_callback(Some(call_back), callback as *mut c_void)
+    })
+    .map(|_| ())
+}
@@ -1,8 +1,23 @@
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use auto_refresh_common::discovery::Announcement;
+use auto_refresh_common::log::{Level, LogRecord};
+use auto_refresh_common::protocol::DEFAULT_PORT;
+use crate::health::{Health, Indicator, HEARTBEAT_INTERVAL};
 use eframe::{egui::*, App};
 
 const RED: Color32 = Color32::from_rgb(255, 0, 0);
 const GREEN: Color32 = Color32::from_rgb(0, 255, 0);
+const YELLOW: Color32 = Color32::from_rgb(255, 255, 0);
+/// How often the console redraws while records may be streaming in
+const CONSOLE_REPAINT_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tab {
+    Main,
+    Console,
+}
 
 #[derive(Debug)]
 pub struct Data {
@@ -10,9 +25,31 @@ pub struct Data {
     pub target_path: String,
     pub is_watching: bool,
     pub switch_ip: String,
+    /// Port of the refresh server, the one a discovered console announced
+    pub server_port: u16,
     pub ftp_port: u16,
     pub ftp_user: String,
     pub ftp_pass: String,
+    pub auth_token: String,
+    pub server_info: String,
+    pub last_summary: String,
+    pub following_pending: bool,
+    pub dump_arc_path: String,
+    pub is_tracing: bool,
+    /// ARC paths to push together, filled from a load trace
+    pub push_list: Vec<String>,
+    /// Push changed files into the server's memory instead of copying them to the target path
+    pub overlay_mode: bool,
+    pub tab: Tab,
+    /// Lowest level the console subscribes to, applied when connecting
+    pub console_level: Level,
+    pub console_connected: bool,
+    pub console_status: String,
+    pub console_lines: VecDeque<LogRecord>,
+    /// Servers that answered the last discovery broadcast
+    pub consoles: Vec<Announcement>,
+    /// Outcome of the latest heartbeats to the console
+    pub health: Health,
 }
 
 pub struct MainApp {
@@ -29,13 +66,36 @@ impl Default for Data {
             is_watching: false,
             // switch_ip: "10.0.0.143".to_owned(),
             switch_ip: "".to_owned(),
+            server_port: DEFAULT_PORT,
             ftp_port: 5000,
             ftp_user: "".to_owned(),
             ftp_pass: "".to_owned(),
+            auth_token: "".to_owned(),
+            server_info: "".to_owned(),
+            last_summary: "".to_owned(),
+            following_pending: false,
+            dump_arc_path: "".to_owned(),
+            is_tracing: false,
+            push_list: vec![],
+            overlay_mode: false,
+            tab: Tab::Main,
+            console_level: Level::Info,
+            console_connected: false,
+            console_status: "".to_owned(),
+            console_lines: VecDeque::new(),
+            consoles: vec![],
+            health: Health::default(),
         }
     }
 }
 
+impl Data {
+    /// Where the refresh server listens, as `ip:port`.
+    pub fn server_address(&self) -> String {
+        format!("{}:{}", self.switch_ip, self.server_port)
+    }
+}
+
 impl Default for MainApp {
     fn default() -> Self {
         Self {
@@ -52,15 +112,8 @@ impl MainApp {
         // for e.g. egui::PaintCallback.
         Self::default()
     }
-}
-
-impl App for MainApp {
-    fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
-        CentralPanel::default().show(ctx, |ui| {
-            ui.vertical_centered(|ui| {
-                ui.heading("Auto-Refresh Client");
-            });
 
+    fn show_main(&mut self, ui: &mut Ui) {
             ui.with_layout(Layout::top_down_justified(Align::Center), |ui| {
                 Grid::new("main_data_grid").show(ui, |ui| {
                     ui.label("Watch Mod Path: ");
@@ -72,7 +125,29 @@ impl App for MainApp {
                     ui.end_row();
     
                     ui.label("Switch IP: ");
-                    ui.text_edit_singleline(&mut self.data.lock().unwrap().switch_ip);
+                    ui.horizontal(|ui| {
+                        let mut data = self.data.lock().unwrap();
+                        ui.add(TextEdit::singleline(&mut data.switch_ip).desired_width(120.0));
+                        if !data.consoles.is_empty() {
+                            let Data { switch_ip, server_port, consoles, .. } = &mut *data;
+                            ComboBox::from_id_source("consoles")
+                                .selected_text("Consoles")
+                                .show_ui(ui, |ui| {
+                                    for console in consoles.iter() {
+                                        let ip = console.ip.to_string();
+                                        let selected = *switch_ip == ip && *server_port == console.port;
+                                        if ui.selectable_label(selected, format!("{}", console)).clicked() {
+                                            *switch_ip = ip;
+                                            *server_port = console.port;
+                                        }
+                                    }
+                                });
+                        }
+                        drop(data);
+                        if ui.button("Find consoles").clicked() {
+                            crate::find_consoles(&self.data);
+                        }
+                    });
                     ui.end_row();
     
                     ui.label("FTP Username: ");
@@ -83,6 +158,14 @@ impl App for MainApp {
                     ui.text_edit_singleline(&mut self.data.lock().unwrap().ftp_pass);
                     ui.end_row();
                     
+                    ui.label("Auth Token: ");
+                    ui.add(TextEdit::singleline(&mut self.data.lock().unwrap().auth_token).password(true));
+                    ui.end_row();
+
+                    ui.label("Server Port: ");
+                    ui.add(Slider::new(&mut self.data.lock().unwrap().server_port, 0..=u16::MAX));
+                    ui.end_row();
+
                     ui.label("FTP Port: ");
                     ui.add(Slider::new(&mut self.data.lock().unwrap().ftp_port, 0..=u16::MAX));
                     ui.end_row();
@@ -93,9 +176,168 @@ impl App for MainApp {
                             let original_state = self.data.lock().unwrap().is_watching;
                             self.data.lock().unwrap().is_watching = !original_state;
                         }
+                        if ui.button("Refresh All").clicked() {
+                            let data = self.data.clone();
+                            let (server, auth_token) = {
+                                let data = data.lock().unwrap();
+                                (data.server_address(), data.auth_token.clone())
+                            };
+                            data.lock().unwrap().last_summary = "Refreshing...".to_owned();
+                            std::thread::spawn(move || {
+                                let summary = match crate::refresh_all_on_server(&server, &auth_token) {
+                                    Ok((hello, summary, cursor)) => {
+                                        crate::note_server_hello(hello, &data);
+                                        if summary.pending_count() > 0 {
+                                            crate::follow_pending(&data, cursor);
+                                        }
+                                        format!("{}", summary)
+                                    }
+                                    Err(e) => e,
+                                };
+                                data.lock().unwrap().last_summary = summary;
+                            });
+                        }
+                        if ui.button("Restore All").clicked() {
+                            let data = self.data.clone();
+                            let (server, auth_token) = {
+                                let data = data.lock().unwrap();
+                                (data.server_address(), data.auth_token.clone())
+                            };
+                            data.lock().unwrap().last_summary = "Restoring...".to_owned();
+                            std::thread::spawn(move || {
+                                let summary = match crate::restore_all_on_server(&server, &auth_token) {
+                                    Ok(summary) => format!("{}", summary),
+                                    Err(e) => e,
+                                };
+                                data.lock().unwrap().last_summary = summary;
+                            });
+                        }
                     });
                     ui.end_row();
                     
+                    ui.label("Dump ARC Path: ");
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut self.data.lock().unwrap().dump_arc_path);
+                        if ui.button("Dump").clicked() {
+                            let data = self.data.clone();
+                            let (server, auth_token, arc_path, dump_root) = {
+                                let data = data.lock().unwrap();
+                                (
+                                    data.server_address(),
+                                    data.auth_token.clone(),
+                                    data.dump_arc_path.clone(),
+                                    crate::dump_root(&data.watch_path),
+                                )
+                            };
+                            data.lock().unwrap().last_summary = "Dumping...".to_owned();
+                            std::thread::spawn(move || {
+                                let summary = match crate::dump_file_from_server(&server, &auth_token, &arc_path, &dump_root) {
+                                    Ok(output_path) => format!("Saved {}", output_path.display()),
+                                    Err(e) => e,
+                                };
+                                data.lock().unwrap().last_summary = summary;
+                            });
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Load Trace: ");
+                    ui.horizontal(|ui| {
+                        let is_tracing = self.data.lock().unwrap().is_tracing;
+                        if ui.button(if is_tracing { "Stop Trace" } else { "Start Trace" }).clicked() {
+                            let data = self.data.clone();
+                            let (server, auth_token, trace_root) = {
+                                let data = data.lock().unwrap();
+                                (data.server_address(), data.auth_token.clone(), crate::trace_root(&data.watch_path))
+                            };
+                            data.lock().unwrap().is_tracing = !is_tracing;
+                            std::thread::spawn(move || {
+                                let summary = if is_tracing {
+                                    match crate::stop_trace_on_server(&server, &auth_token) {
+                                        Ok(entries) => {
+                                            let pushable = crate::fill_push_list(&entries, &data);
+                                            match crate::save_trace(&entries, &trace_root) {
+                                                Ok(saved) => format!(
+                                                    "Traced {} loads, {} files in the push list\nSaved {}",
+                                                    entries.len(),
+                                                    pushable,
+                                                    saved.iter().map(|path| format!("{}", path.display())).collect::<Vec<_>>().join(", ")
+                                                ),
+                                                Err(e) => e,
+                                            }
+                                        }
+                                        Err(e) => e,
+                                    }
+                                } else {
+                                    match crate::start_trace_on_server(&server, &auth_token) {
+                                        Ok(response) => response,
+                                        Err(e) => {
+                                            data.lock().unwrap().is_tracing = false;
+                                            e
+                                        }
+                                    }
+                                };
+                                data.lock().unwrap().last_summary = summary;
+                            });
+                        }
+                        let push_count = self.data.lock().unwrap().push_list.len();
+                        if ui.add_enabled(push_count > 0, Button::new(format!("Push List ({})", push_count))).clicked() {
+                            let data = self.data.clone();
+                            std::thread::spawn(move || {
+                                crate::push_list_to_server(&data);
+                            });
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Overlay: ");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.data.lock().unwrap().overlay_mode, "Keep pushes in memory");
+                        if ui.button("Persist to SD").clicked() {
+                            let data = self.data.clone();
+                            let (server, auth_token) = {
+                                let data = data.lock().unwrap();
+                                (data.server_address(), data.auth_token.clone())
+                            };
+                            data.lock().unwrap().last_summary = "Persisting...".to_owned();
+                            std::thread::spawn(move || {
+                                let summary = match crate::persist_overlay_on_server(&server, &auth_token) {
+                                    Ok(summary) => format!("{}", summary),
+                                    Err(e) => e,
+                                };
+                                data.lock().unwrap().last_summary = summary;
+                            });
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Server:");
+                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                        ui.label(&self.data.lock().unwrap().server_info);
+                    });
+                    ui.end_row();
+
+                    ui.label("Connection:");
+                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                        if ui.button("Test connection").clicked() {
+                            let data = self.data.clone();
+                            data.lock().unwrap().last_summary = "Testing the connection...".to_owned();
+                            std::thread::spawn(move || {
+                                let diagnosis = crate::health::test_connection(&data);
+                                data.lock().unwrap().last_summary = diagnosis;
+                            });
+                        }
+                        let health = self.data.lock().unwrap().health.clone();
+                        let (color, text) = match health.indicator() {
+                            Indicator::Unknown => (Color32::GRAY, "Checking"),
+                            Indicator::Green => (GREEN, "Healthy"),
+                            Indicator::Yellow => (YELLOW, "Degraded"),
+                            Indicator::Red => (RED, "Unreachable"),
+                        };
+                        ui.colored_label(color, text).on_hover_text(health.describe());
+                    });
+                    ui.end_row();
+
                     ui.label("Watcher Status:");
                     ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
                         ui.colored_label(if self.data.lock().unwrap().is_watching {GREEN} else {RED}, format!("{}", if self.data.lock().unwrap().is_watching { "Watching" } else { "Not Watching" }));
@@ -103,6 +345,77 @@ impl App for MainApp {
                     ui.end_row();
                 });
             });
+
+            ScrollArea::vertical().show(ui, |ui| {
+                ui.label(&self.data.lock().unwrap().last_summary);
+            });
+    }
+
+    fn show_console(&mut self, ui: &mut Ui) {
+        let connected = self.data.lock().unwrap().console_connected;
+        ui.horizontal(|ui| {
+            ui.add_enabled_ui(!connected, |ui| {
+                let mut data = self.data.lock().unwrap();
+                let selected = data.console_level.to_token();
+                ComboBox::from_id_source("console_level")
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        for level in Level::ALL {
+                            ui.selectable_value(&mut data.console_level, level, level.to_token());
+                        }
+                    });
+            });
+            if connected {
+                if ui.button("Disconnect").clicked() {
+                    self.data.lock().unwrap().console_connected = false;
+                }
+            } else if ui.button("Connect").clicked() {
+                crate::connect_console(&self.data);
+            }
+            if ui.button("Clear").clicked() {
+                self.data.lock().unwrap().console_lines.clear();
+            }
+            ui.label(&self.data.lock().unwrap().console_status);
         });
+
+        ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
+            for record in self.data.lock().unwrap().console_lines.iter() {
+                let text = RichText::new(format!("{}", record)).monospace();
+                match record.level {
+                    Level::Error => ui.colored_label(RED, text),
+                    Level::Warn => ui.colored_label(YELLOW, text),
+                    Level::Info | Level::Debug => ui.label(text),
+                };
+            }
+        });
+    }
+}
+
+impl App for MainApp {
+    fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
+        CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.heading("Auto-Refresh Client");
+            });
+
+            ui.horizontal(|ui| {
+                let mut data = self.data.lock().unwrap();
+                ui.selectable_value(&mut data.tab, Tab::Main, "Main");
+                ui.selectable_value(&mut data.tab, Tab::Console, "Console");
+            });
+
+            let tab = self.data.lock().unwrap().tab;
+            match tab {
+                Tab::Main => self.show_main(ui),
+                Tab::Console => self.show_console(ui),
+            }
+        });
+
+        if self.data.lock().unwrap().console_connected {
+            ctx.request_repaint_after(CONSOLE_REPAINT_INTERVAL);
+        } else {
+            // Keeps the connection indicator current without input
+            ctx.request_repaint_after(HEARTBEAT_INTERVAL);
+        }
     }
 }
\ No newline at end of file
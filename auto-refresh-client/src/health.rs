@@ -0,0 +1,253 @@
+//! Heartbeats to the console's refresh server and FTP server, so a console that went away
+//! shows up before a push fails.
+
+use auto_refresh_common::protocol::{parse_denied, Command, Request};
+use ftp::FtpStream;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::gui::Data;
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a probe waits to connect, and then for each answer
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Answers slower than this turn the indicator yellow
+const SLOW_LATENCY: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub enum Probe {
+    Up(Duration),
+    /// Answered, but can't be used as configured
+    Refused(Duration, String),
+    Down(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EndpointHealth {
+    pub last_probe: Option<Probe>,
+    /// Last time the endpoint answered at all
+    pub last_seen: Option<Instant>,
+}
+
+impl EndpointHealth {
+    pub fn record(&mut self, probe: Probe) {
+        if !matches!(probe, Probe::Down(_)) {
+            self.last_seen = Some(Instant::now());
+        }
+        self.last_probe = Some(probe);
+    }
+
+    fn is_healthy(&self) -> bool {
+        matches!(self.last_probe, Some(Probe::Up(latency)) if latency <= SLOW_LATENCY)
+    }
+
+    fn is_down(&self) -> bool {
+        matches!(self.last_probe, None | Some(Probe::Down(_)))
+    }
+
+    pub fn describe(&self) -> String {
+        let last_seen = match self.last_seen {
+            Some(last_seen) => format!("last seen {}s ago", last_seen.elapsed().as_secs()),
+            None => "never seen".to_owned(),
+        };
+        match &self.last_probe {
+            None => "not checked yet".to_owned(),
+            Some(Probe::Up(latency)) => format!("up, {} ms, {}", latency.as_millis(), last_seen),
+            Some(Probe::Refused(latency, reason)) => format!("{} ({} ms, {})", reason, latency.as_millis(), last_seen),
+            Some(Probe::Down(reason)) => format!("down: {} ({})", reason, last_seen),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indicator {
+    Unknown,
+    /// Everything in use answers quickly
+    Green,
+    /// Reachable, but slow, refusing requests, or FTP is down
+    Yellow,
+    /// The refresh server can't be reached
+    Red,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    pub refresh: EndpointHealth,
+    pub ftp: EndpointHealth,
+    /// FTP is only probed when the target path is on the console
+    pub uses_ftp: bool,
+}
+
+impl Health {
+    pub fn indicator(&self) -> Indicator {
+        if self.refresh.last_probe.is_none() {
+            return Indicator::Unknown;
+        }
+        if self.refresh.is_down() {
+            return Indicator::Red;
+        }
+        if self.refresh.is_healthy() && (!self.uses_ftp || self.ftp.is_healthy()) {
+            Indicator::Green
+        } else {
+            Indicator::Yellow
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let ftp = if self.uses_ftp { self.ftp.describe() } else { "not used".to_owned() };
+        format!("Refresh server: {}\nFTP: {}", self.refresh.describe(), ftp)
+    }
+}
+
+fn resolve(host: &str, port: u16) -> Result<SocketAddr, String> {
+    (host, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addresses| addresses.next())
+        .ok_or_else(|| format!("{} is not a valid address", host))
+}
+
+fn describe_io_error(address: &SocketAddr, err: &std::io::Error) -> String {
+    match err.kind() {
+        ErrorKind::ConnectionRefused => format!("nothing is listening on {}", address),
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => {
+            format!("no answer from {} within {}s", address, PROBE_TIMEOUT.as_secs())
+        }
+        _ => format!("{}: {}", address, err),
+    }
+}
+
+/// Sends a `PING` request and times the answer.
+pub fn probe_refresh(switch_ip: &str, server_port: u16, token: &str) -> Probe {
+    let address = match resolve(switch_ip, server_port) {
+        Ok(address) => address,
+        Err(e) => return Probe::Down(e),
+    };
+    let request = Request::new(Command::Ping, crate::token_option(token)).to_request();
+    let start = Instant::now();
+    let send = || -> std::io::Result<Vec<u8>> {
+        let mut stream = TcpStream::connect_timeout(&address, PROBE_TIMEOUT)?;
+        stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+        stream.write_all(request.as_bytes())?;
+        stream.shutdown(Shutdown::Write)?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        Ok(response)
+    };
+    match send() {
+        Ok(response) => match parse_denied(&String::from_utf8_lossy(&response)) {
+            Some(reason) => Probe::Refused(start.elapsed(), format!("refused: {}", reason)),
+            None => Probe::Up(start.elapsed()),
+        },
+        Err(e) => Probe::Down(describe_io_error(&address, &e)),
+    }
+}
+
+/// Connects to the FTP server and times its greeting.
+pub fn probe_ftp(switch_ip: &str, ftp_port: u16) -> Probe {
+    let address = match resolve(switch_ip, ftp_port) {
+        Ok(address) => address,
+        Err(e) => return Probe::Down(e),
+    };
+    let start = Instant::now();
+    let greet = || -> std::io::Result<String> {
+        let stream = TcpStream::connect_timeout(&address, PROBE_TIMEOUT)?;
+        stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+        let mut greeting = String::new();
+        BufReader::new(stream).read_line(&mut greeting)?;
+        Ok(greeting)
+    };
+    match greet() {
+        Ok(greeting) if greeting.starts_with("220") => Probe::Up(start.elapsed()),
+        Ok(greeting) => Probe::Refused(
+            start.elapsed(),
+            format!("unexpected greeting \"{}\"", greeting.trim()),
+        ),
+        Err(e) => Probe::Down(describe_io_error(&address, &e)),
+    }
+}
+
+fn uses_ftp(target_path: &str) -> bool {
+    target_path.starts_with("ftp:")
+}
+
+/// Probes both endpoints and records the results.
+fn check(data: &Arc<Mutex<Data>>) -> Option<(Probe, Option<Probe>)> {
+    let (switch_ip, server_port, token, ftp_port, uses_ftp) = {
+        let data = data.lock().unwrap();
+        (data.switch_ip.clone(), data.server_port, data.auth_token.clone(), data.ftp_port, uses_ftp(&data.target_path))
+    };
+    if switch_ip.is_empty() {
+        return None;
+    }
+    let refresh = probe_refresh(&switch_ip, server_port, &token);
+    let ftp = if uses_ftp { Some(probe_ftp(&switch_ip, ftp_port)) } else { None };
+
+    let mut data = data.lock().unwrap();
+    data.health.uses_ftp = uses_ftp;
+    data.health.refresh.record(refresh.clone());
+    if let Some(ftp) = &ftp {
+        data.health.ftp.record(ftp.clone());
+    }
+    Some((refresh, ftp))
+}
+
+pub fn start_heartbeat(data: Arc<Mutex<Data>>) {
+    thread::spawn(move || loop {
+        check(&data);
+        thread::sleep(HEARTBEAT_INTERVAL);
+    });
+}
+
+/// Checks both endpoints right away and explains what's wrong, if anything.
+pub fn test_connection(data: &Arc<Mutex<Data>>) -> String {
+    let (refresh, ftp) = match check(data) {
+        Some(probes) => probes,
+        None => return "Enter the Switch IP first, or use Find consoles.".to_owned(),
+    };
+
+    let mut diagnosis = vec![match refresh {
+        Probe::Up(latency) if latency > SLOW_LATENCY => format!(
+            "Refresh server: OK, but slow ({} ms). The console may be on a weak Wi-Fi signal.",
+            latency.as_millis()
+        ),
+        Probe::Up(latency) => format!("Refresh server: OK ({} ms).", latency.as_millis()),
+        Probe::Refused(_, reason) => format!(
+            "Refresh server: reachable, but it {}. Check the Auth Token against the console's config.",
+            reason
+        ),
+        Probe::Down(reason) => format!(
+            "Refresh server: {}. Check the Switch IP, that the game is running with auto-refresh installed, and that this PC is on the same network.",
+            reason
+        ),
+    }];
+
+    let (switch_ip, ftp_port) = {
+        let data = data.lock().unwrap();
+        (data.switch_ip.clone(), data.ftp_port)
+    };
+    diagnosis.push(match ftp {
+        None => "FTP: not used, the target path isn't an ftp: path.".to_owned(),
+        Some(Probe::Down(reason)) => format!(
+            "FTP: {}. Start the FTP server on the console and check the FTP Port.",
+            reason
+        ),
+        Some(Probe::Refused(_, reason)) => format!("FTP: something answered with an {}. Check the FTP Port.", reason),
+        // Log in the same way uploads do, since that's what they need
+        Some(Probe::Up(latency)) => match FtpStream::connect((switch_ip.as_str(), ftp_port)) {
+            Ok(mut ftp_stream) => {
+                let diagnosis = match ftp_stream.login("", "") {
+                    Ok(()) => format!("FTP: OK ({} ms).", latency.as_millis()),
+                    Err(e) => format!("FTP: reachable, but the login failed: {}", e),
+                };
+                let _ = ftp_stream.quit();
+                diagnosis
+            }
+            Err(e) => format!("FTP: answered once, then failed to connect: {}", e),
+        },
+    });
+    diagnosis.join("\n")
+}
@@ -1,6 +1,16 @@
 #![allow(dead_code)]
 
 mod gui;
+mod health;
+mod settings;
+use auto_refresh_common::arc_path;
+use auto_refresh_common::discovery::{self, Announcement, DISCOVERY_PORT, MAX_DATAGRAM};
+use auto_refresh_common::log::{Level, LogRecord};
+use auto_refresh_common::trace::{self, TraceEntry};
+use auto_refresh_common::protocol::{
+    overlay_request, parse_conflicts, parse_cursor, parse_denied, parse_dump, Command, FileResult, FileStatus,
+    GameSupport, Hello, Request, ServerHello, Summary, DEFAULT_PORT, PROTOCOL_VERSION,
+};
 use eframe::epaint::Vec2;
 use gui::*;
 
@@ -8,14 +18,21 @@ use eframe::{run_native, NativeOptions, HardwareAcceleration, Renderer};
 use ftp::FtpStream;
 use notify::{watcher, DebouncedEvent::*, RecursiveMode, Watcher};
 use nutexb::NutexbFile;
-use std::io::{Cursor, Write};
-use std::net::TcpStream;
+use std::io::{BufRead, BufReader, Cursor, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream, UdpSocket};
 use std::path::*;
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
-const WINDOW_SIZE: Vec2 = Vec2::new(430.0, 210.0);
+use std::time::{Duration, Instant};
+const WINDOW_SIZE: Vec2 = Vec2::new(430.0, 450.0);
+const PENDING_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How often a quiet log stream checks whether the console was disconnected
+const LOG_READ_TIMEOUT: Duration = Duration::from_secs(1);
+/// Records kept in the console, oldest dropped first
+const CONSOLE_CAPACITY: usize = 2000;
+/// How long to wait for consoles to answer a discovery broadcast
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
 
 enum ConversionType {
     Image,
@@ -94,23 +111,428 @@ fn convert_to_nutexb(path: &PathBuf) {
     nutexb.write_to_file(&output_path).unwrap();
 }
 
+fn token_option(token: &str) -> Option<String> {
+    if token.is_empty() { None } else { Some(token.to_owned()) }
+}
+
+/// The token given on the command line, or the one saved from the GUI.
+fn cli_token(arg: Option<&String>) -> String {
+    match arg {
+        Some(token) => token.clone(),
+        None => settings::Settings::load().auth_token,
+    }
+}
+
+/// The refresh server at `switch_ip`, on `DEFAULT_PORT` unless the argument names another port.
+fn cli_server(switch_ip: &str) -> String {
+    if switch_ip.contains(':') {
+        switch_ip.to_owned()
+    } else {
+        format!("{}:{}", switch_ip, DEFAULT_PORT)
+    }
+}
+
+/// Sends `command` and returns the raw response, which is binary for `DUMP`.
+fn send_request(server: &str, token: &str, command: &Command) -> Result<Vec<u8>, String> {
+    let request = Request::new(command.clone(), token_option(token));
+    send_raw_request(server, request.to_request().as_bytes())
+}
+
+fn send_raw_request(server: &str, request: &[u8]) -> Result<Vec<u8>, String> {
+    println!("[send_command] Attempting to connect to {}", server);
+    let send = || -> std::io::Result<Vec<u8>> {
+        let mut stream = TcpStream::connect(server)?;
+        println!("[send_command] Successfully connected to auto-refresh-server!");
+        stream.write_all(request)?;
+        stream.shutdown(Shutdown::Write)?;
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        Ok(response)
+    };
+
+    let response = send().map_err(|e| format!("Failed to connect: {}", e))?;
+    match parse_denied(&String::from_utf8_lossy(&response)) {
+        Some(reason) => Err(format!("Server refused the request: {}", reason)),
+        None => Ok(response),
+    }
+}
+
+fn send_command(server: &str, token: &str, command: &Command) -> Result<String, String> {
+    send_request(server, token, command)
+        .map(|response| String::from_utf8_lossy(&response).into_owned())
+}
+
+/// Broadcasts a discovery request on the LAN and collects the servers answering it.
+pub fn discover_consoles() -> Result<Vec<Announcement>, String> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).map_err(|e| format!("Failed to open a UDP socket: {}", e))?;
+    socket.set_broadcast(true).map_err(|e| format!("Failed to enable broadcasts: {}", e))?;
+    socket
+        .send_to(discovery::discover_request().as_bytes(), ("255.255.255.255", DISCOVERY_PORT))
+        .map_err(|e| format!("Failed to send the discovery broadcast: {}", e))?;
+
+    let deadline = Instant::now() + DISCOVERY_TIMEOUT;
+    let mut consoles: Vec<Announcement> = vec![];
+    let mut buffer = [0; MAX_DATAGRAM];
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        if remaining.is_zero() || socket.set_read_timeout(Some(remaining)).is_err() {
+            break;
+        }
+        let (size, source) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+            Err(e) => return Err(format!("Failed to read discovery answers: {}", e)),
+        };
+        let mut announcement = match Announcement::parse_line(&String::from_utf8_lossy(&buffer[..size])) {
+            Some(announcement) => announcement,
+            None => continue,
+        };
+        if announcement.ip.is_unspecified() {
+            announcement.ip = source.ip();
+        }
+        if !consoles.iter().any(|console| console.ip == announcement.ip) {
+            consoles.push(announcement);
+        }
+    }
+    Ok(consoles)
+}
+
+/// Looks for consoles in the background, filling `switch_ip` right away if exactly one answers.
+pub fn find_consoles(data: &Arc<Mutex<Data>>) {
+    let data = data.clone();
+    data.lock().unwrap().last_summary = "Looking for consoles...".to_owned();
+    thread::spawn(move || {
+        let summary = match discover_consoles() {
+            Ok(consoles) => {
+                let mut data = data.lock().unwrap();
+                if consoles.len() == 1 {
+                    data.switch_ip = consoles[0].ip.to_string();
+                    data.server_port = consoles[0].port;
+                }
+                let summary = match consoles.len() {
+                    0 => "No consoles answered. Is the game running with auto-refresh, on the same network?".to_owned(),
+                    1 => format!("Found {}", consoles[0]),
+                    count => format!("Found {} consoles, pick one next to the Switch IP", count),
+                };
+                data.consoles = consoles;
+                summary
+            }
+            Err(e) => e,
+        };
+        data.lock().unwrap().last_summary = summary;
+    });
+}
+
+/// Subscribes to the server's log and hands each record to `on_record` for as long as
+/// `keep_streaming` returns true.
+fn stream_logs(
+    server: &str,
+    token: &str,
+    level: Level,
+    keep_streaming: impl Fn() -> bool,
+    mut on_record: impl FnMut(LogRecord),
+) -> Result<(), String> {
+    let request = Request::new(Command::LogSubscribe(level), token_option(token));
+    let connect = || -> std::io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(server)?;
+        stream.write_all(request.to_request().as_bytes())?;
+        stream.shutdown(Shutdown::Write)?;
+        stream.set_read_timeout(Some(LOG_READ_TIMEOUT))?;
+        Ok(stream)
+    };
+    let stream = connect().map_err(|e| format!("Failed to connect: {}", e))?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = vec![];
+    while keep_streaming() {
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => return Err("Server closed the log stream".to_owned()),
+            Ok(_) => {
+                let text = String::from_utf8_lossy(&line).into_owned();
+                line.clear();
+                if let Some(reason) = parse_denied(&text) {
+                    return Err(format!("Server refused the request: {}", reason));
+                }
+                if let Some(record) = LogRecord::parse_line(&text) {
+                    on_record(record);
+                }
+            }
+            // A partial line stays in `line` until the rest arrives
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => return Err(format!("Log stream failed: {}", e)),
+        }
+    }
+    Ok(())
+}
+
+/// Streams the server's log into the console until it's disconnected.
+pub fn connect_console(data: &Arc<Mutex<Data>>) {
+    let (server, auth_token, level) = {
+        let mut data = data.lock().unwrap();
+        if data.console_connected {
+            return;
+        }
+        data.console_connected = true;
+        data.console_status = "Connected".to_owned();
+        (data.server_address(), data.auth_token.clone(), data.console_level)
+    };
+    let data = data.clone();
+    thread::spawn(move || {
+        let result = stream_logs(
+            &server,
+            &auth_token,
+            level,
+            || data.lock().unwrap().console_connected,
+            |record| {
+                let mut data = data.lock().unwrap();
+                if data.console_lines.len() >= CONSOLE_CAPACITY {
+                    data.console_lines.pop_front();
+                }
+                data.console_lines.push_back(record);
+            },
+        );
+        let mut data = data.lock().unwrap();
+        data.console_connected = false;
+        data.console_status = match result {
+            Ok(()) => "Disconnected".to_owned(),
+            Err(e) => e,
+        };
+    });
+}
+
+/// Output is saved next to the watch root rather than inside it, so the watcher doesn't push
+/// it back.
+fn folder_next_to_watch_root(watch_path: &str, name: &str) -> PathBuf {
+    match Path::new(watch_path).parent() {
+        Some(parent) => parent.join(name),
+        None => PathBuf::from(name),
+    }
+}
+
+pub fn dump_root(watch_path: &str) -> PathBuf {
+    folder_next_to_watch_root(watch_path, "dump")
+}
+
+pub fn trace_root(watch_path: &str) -> PathBuf {
+    folder_next_to_watch_root(watch_path, "trace")
+}
+
+/// Saves the bytes the game has loaded for `arc_path` under `dump_root`.
+pub fn dump_file_from_server(
+    server: &str,
+    token: &str,
+    arc_path: &str,
+    dump_root: &Path,
+) -> Result<PathBuf, String> {
+    let arc_path = arc_path::normalize(arc_path).map_err(|e| e.to_string())?;
+    let response = send_request(server, token, &Command::Dump(arc_path.clone()))?;
+    let dump = parse_dump(&response)?;
+    if dump.path != arc_path {
+        return Err(format!("Server sent {} instead of the requested {}", dump.path, arc_path));
+    }
+
+    // Only ever the path that was asked for, never one taken from the response
+    let output_path = dump_root.join(arc_path::to_sd_path(&arc_path));
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed creating dump folder: {}", e))?;
+    }
+    std::fs::write(&output_path, dump.data).map_err(|e| format!("Failed writing dump: {}", e))?;
+    Ok(output_path)
+}
+
 fn refresh_file_on_server(arc_path: &PathBuf, data: &Arc<Mutex<Data>>) {
-    let address = format!("{}:7878", data.lock().unwrap().switch_ip);
-    println!("[refresh_file_on_server] Attempting to connect to {}", address);
-    match TcpStream::connect("10.0.0.143:7878") {
-        Ok(mut stream) => {
-            println!("[refresh_file_on_server] Successfully connected to auto-refresh-server!");
-            stream
-                .write(
-                    format!("{}\n", arc_path.display())
-                        .replace("\\", "/")
-                        .as_bytes(),
-                )
-                .unwrap();
+    let (server, auth_token) = {
+        let data = data.lock().unwrap();
+        (data.server_address(), data.auth_token.clone())
+    };
+    let arc_path = match arc_path::normalize(&format!("{}", arc_path.display())) {
+        Ok(arc_path) => arc_path,
+        Err(err) => {
+            println!("[refresh_file_on_server] Not refreshing {}: {}", arc_path.display(), err);
+            return;
+        }
+    };
+    let command = Command::Refresh(vec![arc_path]);
+    match send_command(&server, &auth_token, &command) {
+        Ok(response) => {
+            note_server_hello(ServerHello::from_response(&response), data);
+            let summary = Summary::parse(&response);
+            print!("[refresh_file_on_server] {}", summary);
+            if summary.pending_count() > 0 {
+                follow_pending(data, parse_cursor(&response));
+            }
         }
+        Err(e) => println!("[refresh_file_on_server] {}", e),
+    }
+}
+
+/// Sends the file's bytes to be kept in the server's memory instead of writing them to the SD.
+pub fn overlay_file_on_server(server: &str, token: &str, arc_path: &str, contents: &[u8]) -> Result<FileResult, String> {
+    let request = overlay_request(Some(&Hello::new(token_option(token))), arc_path, contents);
+    let response = send_raw_request(server, &request)?;
+    let response = String::from_utf8_lossy(&response);
+    Summary::parse(&response)
+        .results
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("Unexpected response: {}", response))
+}
+
+fn overlay_path(path: &Path, arc_path: &Path, data: &Arc<Mutex<Data>>) {
+    let (server, auth_token) = {
+        let data = data.lock().unwrap();
+        (data.server_address(), data.auth_token.clone())
+    };
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
         Err(e) => {
-            println!("[refresh_file_on_server] Failed to connect: {}", e);
+            println!("[overlay_path] Failed reading {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let arc_path = format!("{}", arc_path.display());
+    match overlay_file_on_server(&server, &auth_token, &arc_path, &contents) {
+        Ok(result) => {
+            println!("[overlay_path] {}", result);
+            data.lock().unwrap().last_summary = format!("{}", result);
+        }
+        Err(e) => println!("[overlay_path] {}", e),
+    }
+}
+
+pub fn persist_overlay_on_server(server: &str, token: &str) -> Result<Summary, String> {
+    send_command(server, token, &Command::Persist(vec![])).map(|response| Summary::parse(&response))
+}
+
+/// The server as it reported itself, followed by a warning if it speaks another protocol version.
+fn describe_server(hello: &ServerHello) -> String {
+    if hello.version == PROTOCOL_VERSION {
+        return format!("{}", hello);
+    }
+    let outdated = if hello.version < PROTOCOL_VERSION { "the plugin" } else { "this client" };
+    format!("{}, protocol version {} but this client speaks {}, update {}", hello, hello.version, PROTOCOL_VERSION, outdated)
+}
+
+/// Shows what the server reported about itself, warning if its offsets can't be trusted or it
+/// speaks another protocol version.
+pub fn note_server_hello(hello: Option<ServerHello>, data: &Arc<Mutex<Data>>) {
+    if let Some(hello) = hello {
+        let description = describe_server(&hello);
+        if hello.game_support != Some(GameSupport::Verified) || hello.version != PROTOCOL_VERSION {
+            println!("[note_server_hello] Warning: {}", description);
         }
+        data.lock().unwrap().server_info = description;
+    }
+}
+
+/// Polls the server until the refreshes it deferred for unloaded files are resolved,
+/// reporting each outcome as it comes in. `cursor` is the one the refresh reply ended with.
+pub fn follow_pending(data: &Arc<Mutex<Data>>, cursor: Option<u64>) {
+    {
+        let mut data = data.lock().unwrap();
+        if data.following_pending {
+            return;
+        }
+        data.following_pending = true;
+    }
+    let data = data.clone();
+    thread::spawn(move || {
+        let mut cursor = cursor;
+        loop {
+            thread::sleep(PENDING_POLL_INTERVAL);
+            let (server, auth_token) = {
+                let data = data.lock().unwrap();
+                (data.server_address(), data.auth_token.clone())
+            };
+            match send_command(&server, &auth_token, &Command::Pending(cursor)) {
+                Ok(response) => {
+                    cursor = parse_cursor(&response);
+                    let summary = Summary::parse(&response);
+                    for result in &summary.results {
+                        if !matches!(result.status, FileStatus::Pending(_)) {
+                            println!("[follow_pending] {}", result);
+                            data.lock().unwrap().last_summary.push_str(&format!("{}\n", result));
+                        }
+                    }
+                    if summary.pending_count() == 0 {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    println!("[follow_pending] {}", e);
+                    break;
+                }
+            }
+        }
+        data.lock().unwrap().following_pending = false;
+    });
+}
+
+/// The server's HELLO, the summary and the cursor to follow the deferred refreshes from.
+pub fn refresh_all_on_server(server: &str, token: &str) -> Result<(Option<ServerHello>, Summary, Option<u64>), String> {
+    send_command(server, token, &Command::RefreshAll)
+        .map(|response| (ServerHello::from_response(&response), Summary::parse(&response), parse_cursor(&response)))
+}
+
+pub fn restore_all_on_server(server: &str, token: &str) -> Result<Summary, String> {
+    send_command(server, token, &Command::RestoreAll).map(|response| Summary::parse(&response))
+}
+
+pub fn start_trace_on_server(server: &str, token: &str) -> Result<String, String> {
+    send_command(server, token, &Command::TraceStart)
+}
+
+pub fn stop_trace_on_server(server: &str, token: &str) -> Result<Vec<TraceEntry>, String> {
+    send_command(server, token, &Command::TraceStop)
+        .map(|response| trace::parse_response(&response))
+}
+
+/// Writes the trace as CSV or JSON, picked from the extension of `output_path`.
+pub fn export_trace(entries: &[TraceEntry], output_path: &Path) -> Result<(), String> {
+    let contents = match output_path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => trace::to_json(entries),
+        _ => trace::to_csv(entries),
+    };
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed creating trace folder: {}", e))?;
+    }
+    std::fs::write(output_path, contents).map_err(|e| format!("Failed writing trace: {}", e))
+}
+
+/// Saves the trace as both CSV and JSON under `trace_root`, named after the current time.
+pub fn save_trace(entries: &[TraceEntry], trace_root: &Path) -> Result<Vec<PathBuf>, String> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let mut saved = vec![];
+    for extension in &["csv", "json"] {
+        let output_path = trace_root.join(format!("trace-{}.{}", timestamp, extension));
+        export_trace(entries, &output_path)?;
+        saved.push(output_path);
+    }
+    Ok(saved)
+}
+
+/// Fills the push list with the traced files that exist under the watch root.
+pub fn fill_push_list(entries: &[TraceEntry], data: &Arc<Mutex<Data>>) -> usize {
+    let mut data = data.lock().unwrap();
+    let watch_path = PathBuf::from(&data.watch_path);
+    data.push_list = trace::loaded_paths(entries)
+        .into_iter()
+        .filter(|path| watch_path.join(arc_path::to_sd_path(path)).is_file())
+        .collect();
+    data.push_list.len()
+}
+
+/// Pushes and refreshes every file of the push list, as if the watcher had seen it change.
+pub fn push_list_to_server(data: &Arc<Mutex<Data>>) {
+    let (watch_path, push_list) = {
+        let data = data.lock().unwrap();
+        (PathBuf::from(&data.watch_path), data.push_list.clone())
+    };
+    for path in push_list {
+        handle_path(&watch_path.join(arc_path::to_sd_path(&path)), data);
     }
 }
 
@@ -180,7 +602,17 @@ fn handle_path(path: &PathBuf, data: &Arc<Mutex<Data>>) {
     }
 
     let arc_path = PathBuf::from(&format!("{}", path.display())[data.lock().unwrap().watch_path.len() + 1..]);
-    
+    if let Err(err) = arc_path::normalize(&format!("{}", arc_path.display())) {
+        println!("[handle_path] Not pushing {}: {}", arc_path.display(), err);
+        return;
+    }
+
+    if data.lock().unwrap().overlay_mode {
+        println!("[handle_path] Pushing {} into the overlay", arc_path.display());
+        overlay_path(path, &arc_path, data);
+        return;
+    }
+
     if data.lock().unwrap().target_path.starts_with("ftp:") {
         // Upload path to FTP
         println!("Uploading to ftp!");
@@ -232,9 +664,252 @@ fn setup_watcher(data: Arc<Mutex<Data>>) {
     }
 }
 
+fn print_usage() {
+    println!("Usage:");
+    println!("  auto-refresh-client                                  Open the GUI");
+    println!("  auto-refresh-client refresh-all <switch ip> [token]  Refresh every file in the mod folders");
+    println!("  auto-refresh-client rescan <switch ip> [token]       Re-index the mod folders and list conflicting files");
+    println!("  auto-refresh-client status <switch ip> [token]       Show the server's diagnostics");
+    println!("  auto-refresh-client pending <switch ip> [token]      Show refreshes waiting for their file to load");
+    println!("  auto-refresh-client restore <switch ip> <arc path> [token]");
+    println!("                                                       Restore a file to its contents before the first refresh");
+    println!("  auto-refresh-client restore-all <switch ip> [token]  Restore every refreshed file");
+    println!("  auto-refresh-client trace-start <switch ip> [token]  Start recording the files the game loads");
+    println!("  auto-refresh-client trace-stop <switch ip> <output .csv or .json> [token]");
+    println!("                                                       Stop recording and export the trace");
+    println!("  auto-refresh-client dump <switch ip> <arc path> [token]");
+    println!("                                                       Save the file's loaded bytes under ./dump");
+    println!("  auto-refresh-client logs <switch ip> [level] [token] Print the server's log as it is written");
+    println!("  auto-refresh-client overlay <switch ip> <arc path> <file> [token]");
+    println!("                                                       Keep the file in the server's memory, served on its next load");
+    println!("  auto-refresh-client persist <switch ip> [token]      Write every overlaid file to the SD card");
+    println!("  auto-refresh-client discover                         List the consoles running auto-refresh on the network");
+    println!("A <switch ip> may end with :<port> if the server doesn't listen on port {}.", DEFAULT_PORT);
+    println!("Without a [token], the one saved from the GUI is sent.");
+}
+
+/// Runs a subcommand without opening the GUI. Returns false if no subcommand was given.
+fn run_cli(args: &[String]) -> bool {
+    match args.first().map(String::as_str) {
+        None => false,
+        Some("refresh-all") => {
+            match args.get(1) {
+                Some(switch_ip) => {
+                    let server = &cli_server(switch_ip);
+                    let token = &cli_token(args.get(2));
+                    match refresh_all_on_server(server, token) {
+                        Ok((hello, summary, _)) => {
+                            if let Some(hello) = hello {
+                                println!("{}", describe_server(&hello));
+                            }
+                            print!("{}", summary);
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                None => print_usage(),
+            }
+            true
+        }
+        Some("rescan") => {
+            match args.get(1) {
+                Some(switch_ip) => {
+                    let server = &cli_server(switch_ip);
+                    let token = &cli_token(args.get(2));
+                    match send_command(server, token, &Command::Rescan) {
+                        Ok(response) => {
+                            let counts = response.lines().find(|line| line.starts_with("Rescanned"));
+                            println!("{}", counts.unwrap_or("The server did not report what it indexed."));
+                            for conflict in parse_conflicts(&response) {
+                                println!("Conflict: {}", conflict);
+                            }
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                None => print_usage(),
+            }
+            true
+        }
+        Some("status") => {
+            match args.get(1) {
+                Some(switch_ip) => {
+                    let server = &cli_server(switch_ip);
+                    let token = &cli_token(args.get(2));
+                    match send_command(server, token, &Command::Status) {
+                        Ok(response) => print!("{}", response),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                None => print_usage(),
+            }
+            true
+        }
+        Some("pending") => {
+            match args.get(1) {
+                Some(switch_ip) => {
+                    let server = &cli_server(switch_ip);
+                    let token = &cli_token(args.get(2));
+                    match send_command(server, token, &Command::Pending(None)) {
+                        Ok(response) => print!("{}", Summary::parse(&response)),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                None => print_usage(),
+            }
+            true
+        }
+        Some("restore") => {
+            match (args.get(1), args.get(2)) {
+                (Some(switch_ip), Some(arc_path)) => {
+                    let server = &cli_server(switch_ip);
+                    let token = &cli_token(args.get(3));
+                    let command = Command::Restore(vec![arc_path.clone()]);
+                    match send_command(server, token, &command) {
+                        Ok(response) => print!("{}", Summary::parse(&response)),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                _ => print_usage(),
+            }
+            true
+        }
+        Some("trace-start") => {
+            match args.get(1) {
+                Some(switch_ip) => {
+                    let server = &cli_server(switch_ip);
+                    let token = &cli_token(args.get(2));
+                    match start_trace_on_server(server, token) {
+                        Ok(response) => print!("{}", response),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                None => print_usage(),
+            }
+            true
+        }
+        Some("trace-stop") => {
+            match (args.get(1), args.get(2)) {
+                (Some(switch_ip), Some(output_path)) => {
+                    let server = &cli_server(switch_ip);
+                    let token = &cli_token(args.get(3));
+                    let exported = stop_trace_on_server(server, token).and_then(|entries| {
+                        export_trace(&entries, Path::new(output_path)).map(|_| entries.len())
+                    });
+                    match exported {
+                        Ok(count) => println!("Saved {} entries to {}", count, output_path),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                _ => print_usage(),
+            }
+            true
+        }
+        Some("dump") => {
+            match (args.get(1), args.get(2)) {
+                (Some(switch_ip), Some(arc_path)) => {
+                    let server = &cli_server(switch_ip);
+                    let token = &cli_token(args.get(3));
+                    match dump_file_from_server(server, token, arc_path, Path::new("dump")) {
+                        Ok(output_path) => println!("Saved {}", output_path.display()),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                _ => print_usage(),
+            }
+            true
+        }
+        Some("logs") => {
+            match args.get(1) {
+                Some(switch_ip) => {
+                    let server = &cli_server(switch_ip);
+                    let (level, token) = match args.get(2).and_then(|arg| Level::parse_token(arg)) {
+                        Some(level) => (level, args.get(3)),
+                        None => (Level::Info, args.get(2)),
+                    };
+                    let token = &cli_token(token);
+                    if let Err(e) = stream_logs(server, token, level, || true, |record| println!("{}", record)) {
+                        println!("{}", e);
+                    }
+                }
+                None => print_usage(),
+            }
+            true
+        }
+        Some("overlay") => {
+            match (args.get(1), args.get(2), args.get(3)) {
+                (Some(switch_ip), Some(arc_path), Some(file)) => {
+                    let server = &cli_server(switch_ip);
+                    let token = &cli_token(args.get(4));
+                    let pushed = std::fs::read(file)
+                        .map_err(|e| format!("Failed reading {}: {}", file, e))
+                        .and_then(|contents| overlay_file_on_server(server, token, arc_path, &contents));
+                    match pushed {
+                        Ok(result) => println!("{}", result),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                _ => print_usage(),
+            }
+            true
+        }
+        Some("persist") => {
+            match args.get(1) {
+                Some(switch_ip) => {
+                    let server = &cli_server(switch_ip);
+                    let token = &cli_token(args.get(2));
+                    match persist_overlay_on_server(server, token) {
+                        Ok(summary) => print!("{}", summary),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                None => print_usage(),
+            }
+            true
+        }
+        Some("discover") => {
+            match discover_consoles() {
+                Ok(consoles) if consoles.is_empty() => println!("No consoles answered"),
+                Ok(consoles) => {
+                    for console in consoles {
+                        println!("{}", console);
+                    }
+                }
+                Err(e) => println!("{}", e),
+            }
+            true
+        }
+        Some("restore-all") => {
+            match args.get(1) {
+                Some(switch_ip) => {
+                    let server = &cli_server(switch_ip);
+                    let token = &cli_token(args.get(2));
+                    match restore_all_on_server(server, token) {
+                        Ok(summary) => print!("{}", summary),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                None => print_usage(),
+            }
+            true
+        }
+        Some(_) => {
+            print_usage();
+            true
+        }
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if run_cli(&args) {
+        return;
+    }
+
     let app = MainApp::default();
+    settings::Settings::load().apply_to(&mut app.data.lock().unwrap());
     let ref_data = app.data.clone();
+    let saved_data = app.data.clone();
     let win_option = NativeOptions {
         always_on_top: false,
         maximized: false,
@@ -259,6 +934,7 @@ fn main() {
         run_and_return: true,
     };
 
+    health::start_heartbeat(ref_data.clone());
     thread::spawn(move || {
         setup_watcher(ref_data);
     });
@@ -266,6 +942,7 @@ fn main() {
         "Auto-Refresh Client",
         win_option,
         Box::new(|_cc| Box::new(app)),
-    )
+    );
+    settings::Settings::from_data(&saved_data.lock().unwrap()).save();
     // setup_watcher();
 }
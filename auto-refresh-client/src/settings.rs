@@ -0,0 +1,100 @@
+//! Connection settings kept between runs, in `auto-refresh-client.cfg` next to the executable.
+//!
+//! One `key = value` per line, like the server's config. The CLI reads the saved token when none
+//! is given on the command line.
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::gui::Data;
+
+const FILE_NAME: &str = "auto-refresh-client.cfg";
+
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    pub watch_path: String,
+    pub target_path: String,
+    pub switch_ip: String,
+    pub server_port: Option<u16>,
+    pub ftp_port: Option<u16>,
+    pub ftp_user: String,
+    pub auth_token: String,
+}
+
+fn settings_path() -> Option<PathBuf> {
+    Some(std::env::current_exe().ok()?.with_file_name(FILE_NAME))
+}
+
+impl Settings {
+    /// Reads the saved settings, or the defaults if there are none.
+    pub fn load() -> Self {
+        let text = match settings_path().map(fs::read_to_string) {
+            Some(Ok(text)) => text,
+            _ => return Self::default(),
+        };
+        let mut settings = Self::default();
+        for line in text.lines() {
+            let (key, value) = match line.find('=') {
+                Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+                None => continue,
+            };
+            match key {
+                "watch_path" => settings.watch_path = value.to_owned(),
+                "target_path" => settings.target_path = value.to_owned(),
+                "switch_ip" => settings.switch_ip = value.to_owned(),
+                "server_port" => settings.server_port = value.parse().ok(),
+                "ftp_port" => settings.ftp_port = value.parse().ok(),
+                "ftp_user" => settings.ftp_user = value.to_owned(),
+                "token" => settings.auth_token = value.to_owned(),
+                _ => println!("[settings] Ignoring unknown setting {}", key),
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) {
+        let path = match settings_path() {
+            Some(path) => path,
+            None => return,
+        };
+        let mut text = format!(
+            "watch_path = {}\ntarget_path = {}\nswitch_ip = {}\nftp_user = {}\ntoken = {}\n",
+            self.watch_path, self.target_path, self.switch_ip, self.ftp_user, self.auth_token
+        );
+        if let Some(server_port) = self.server_port {
+            text.push_str(&format!("server_port = {}\n", server_port));
+        }
+        if let Some(ftp_port) = self.ftp_port {
+            text.push_str(&format!("ftp_port = {}\n", ftp_port));
+        }
+        if let Err(err) = fs::write(&path, text) {
+            println!("[settings] Failed saving {}: {}", path.display(), err);
+        }
+    }
+
+    pub fn from_data(data: &Data) -> Self {
+        Self {
+            watch_path: data.watch_path.clone(),
+            target_path: data.target_path.clone(),
+            switch_ip: data.switch_ip.clone(),
+            server_port: Some(data.server_port),
+            ftp_port: Some(data.ftp_port),
+            ftp_user: data.ftp_user.clone(),
+            auth_token: data.auth_token.clone(),
+        }
+    }
+
+    pub fn apply_to(&self, data: &mut Data) {
+        data.watch_path = self.watch_path.clone();
+        data.target_path = self.target_path.clone();
+        data.switch_ip = self.switch_ip.clone();
+        if let Some(server_port) = self.server_port {
+            data.server_port = server_port;
+        }
+        if let Some(ftp_port) = self.ftp_port {
+            data.ftp_port = ftp_port;
+        }
+        data.ftp_user = self.ftp_user.clone();
+        data.auth_token = self.auth_token.clone();
+    }
+}
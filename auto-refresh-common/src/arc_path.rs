@@ -0,0 +1,207 @@
+//! Validation for ARC paths received from or sent over the network.
+//!
+//! An ARC path is relative to a mod root, uses `/` separators and is entirely lowercase,
+//! e.g. `ui/replace/chara/chara_0/chara_0_mario_00.bntx`.
+//!
+//! Streamed files (videos, some audio) live under `stream:/` in the ARC, e.g.
+//! `stream:/sound/bgm/bgm_a01_menu.nus3audio`. Mod folders spell that `stream;/`, since `:`
+//! can't be used in a folder name on the SD card.
+
+use std::fmt;
+
+pub const STREAM_PREFIX: &str = "stream:/";
+pub const SD_STREAM_PREFIX: &str = "stream;/";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArcPathError {
+    Empty,
+    /// Starts at a filesystem root or names a device, e.g. `/ui`, `sd:/ui` or `C:\ui`
+    Absolute,
+    /// Contains a `..` segment
+    Traversal,
+    NotLowercase,
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for ArcPathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArcPathError::Empty => write!(f, "path is empty"),
+            ArcPathError::Absolute => write!(f, "path must be relative to the mod folder"),
+            ArcPathError::Traversal => write!(f, "path must not contain '..'"),
+            ArcPathError::NotLowercase => write!(f, "path must be lowercase"),
+            ArcPathError::InvalidCharacter(c) => write!(f, "path contains invalid character {:?}", c),
+        }
+    }
+}
+
+impl std::error::Error for ArcPathError {}
+
+/// Turns `path` into a canonical ARC path: `\` becomes `/`, and empty or `.` segments are
+/// dropped. Anything that could point outside of a mod root is rejected. Stream paths keep
+/// their `stream:/` prefix, whichever way they were spelled.
+pub fn normalize(path: &str) -> Result<String, ArcPathError> {
+    let path = path.trim().replace('\\', "/");
+    let (prefix, path) = if path.starts_with(STREAM_PREFIX) || path.starts_with(SD_STREAM_PREFIX) {
+        (STREAM_PREFIX, &path[STREAM_PREFIX.len()..])
+    } else {
+        ("", path.as_str())
+    };
+
+    if let Some(c) = path.chars().find(|c| c.is_control()) {
+        return Err(ArcPathError::InvalidCharacter(c));
+    }
+    if path.starts_with('/') || path.contains(':') {
+        return Err(ArcPathError::Absolute);
+    }
+
+    let mut segments = vec![];
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return Err(ArcPathError::Traversal),
+            _ => segments.push(segment),
+        }
+    }
+
+    if segments.is_empty() {
+        return Err(ArcPathError::Empty);
+    }
+
+    let path = segments.join("/");
+    if path.chars().any(char::is_uppercase) {
+        return Err(ArcPathError::NotLowercase);
+    }
+
+    Ok(format!("{}{}", prefix, path))
+}
+
+pub fn is_stream(path: &str) -> bool {
+    path.starts_with(STREAM_PREFIX)
+}
+
+/// Where a normalized ARC path lives inside a mod folder.
+pub fn to_sd_path(path: &str) -> String {
+    if is_stream(path) {
+        path.replacen(STREAM_PREFIX, SD_STREAM_PREFIX, 1)
+    } else {
+        path.to_string()
+    }
+}
+
+/// The ARC path of a file inside a mod folder, the inverse of `to_sd_path`.
+pub fn from_sd_path(path: &str) -> String {
+    if path.starts_with(SD_STREAM_PREFIX) {
+        path.replacen(SD_STREAM_PREFIX, STREAM_PREFIX, 1)
+    } else {
+        path.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_plain_paths() {
+        assert_eq!(
+            normalize("ui/replace/chara/chara_0/chara_0_mario_00.bntx"),
+            Ok("ui/replace/chara/chara_0/chara_0_mario_00.bntx".to_string())
+        );
+        assert_eq!(
+            normalize("  fighter\\mario\\model.numdlb \n"),
+            Ok("fighter/mario/model.numdlb".to_string())
+        );
+    }
+
+    #[test]
+    fn drops_dot_and_empty_segments() {
+        assert_eq!(
+            normalize("ui/./replace//chara/"),
+            Ok("ui/replace/chara".to_string())
+        );
+        assert_eq!(normalize("./ui"), Ok("ui".to_string()));
+        assert_eq!(normalize("./"), Err(ArcPathError::Empty));
+        assert_eq!(normalize(""), Err(ArcPathError::Empty));
+    }
+
+    #[test]
+    fn rejects_traversal() {
+        assert_eq!(normalize(".."), Err(ArcPathError::Traversal));
+        assert_eq!(normalize("ui/../../boot.nro"), Err(ArcPathError::Traversal));
+        assert_eq!(normalize("ui\\..\\x"), Err(ArcPathError::Traversal));
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        assert_eq!(normalize("/ui/a.bntx"), Err(ArcPathError::Absolute));
+        assert_eq!(normalize("\\ui\\a.bntx"), Err(ArcPathError::Absolute));
+        assert_eq!(
+            normalize("sd:/ultimate/mods/a.bntx"),
+            Err(ArcPathError::Absolute)
+        );
+        assert_eq!(normalize("C:\\ui\\a.bntx"), Err(ArcPathError::Absolute));
+        assert_eq!(normalize("ui/a:b"), Err(ArcPathError::Absolute));
+    }
+
+    #[test]
+    fn rejects_control_characters() {
+        assert_eq!(
+            normalize("ui/a\0.bntx"),
+            Err(ArcPathError::InvalidCharacter('\0'))
+        );
+        assert_eq!(
+            normalize("ui/a\tb.bntx"),
+            Err(ArcPathError::InvalidCharacter('\t'))
+        );
+    }
+
+    #[test]
+    fn rejects_uppercase() {
+        assert_eq!(normalize("UI/a.bntx"), Err(ArcPathError::NotLowercase));
+        assert_eq!(
+            normalize("fighter/Mario/model.numdlb"),
+            Err(ArcPathError::NotLowercase)
+        );
+    }
+
+    #[test]
+    fn keeps_stream_prefixes() {
+        assert_eq!(
+            normalize("stream:/sound/bgm/bgm_a01.nus3audio"),
+            Ok("stream:/sound/bgm/bgm_a01.nus3audio".to_string())
+        );
+        assert_eq!(
+            normalize("stream;/sound/bgm/bgm_a01.nus3audio"),
+            Ok("stream:/sound/bgm/bgm_a01.nus3audio".to_string())
+        );
+        assert_eq!(
+            normalize("stream;\\movie\\a.webm"),
+            Ok("stream:/movie/a.webm".to_string())
+        );
+        assert!(is_stream("stream:/movie/a.webm"));
+        assert!(!is_stream("ui/stream:/a"));
+    }
+
+    #[test]
+    fn validates_stream_paths_after_the_prefix() {
+        assert_eq!(
+            normalize("stream:/../boot.nro"),
+            Err(ArcPathError::Traversal)
+        );
+        assert_eq!(normalize("stream:/"), Err(ArcPathError::Empty));
+        assert_eq!(normalize("stream:sound/a"), Err(ArcPathError::Absolute));
+        assert_eq!(
+            normalize("stream:/Sound/a.nus3audio"),
+            Err(ArcPathError::NotLowercase)
+        );
+    }
+
+    #[test]
+    fn converts_stream_paths_to_and_from_sd() {
+        assert_eq!(to_sd_path("stream:/movie/a.webm"), "stream;/movie/a.webm");
+        assert_eq!(from_sd_path("stream;/movie/a.webm"), "stream:/movie/a.webm");
+        assert_eq!(to_sd_path("ui/a.bntx"), "ui/a.bntx");
+        assert_eq!(from_sd_path("ui/a.bntx"), "ui/a.bntx");
+    }
+}
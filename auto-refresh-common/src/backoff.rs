@@ -0,0 +1,76 @@
+//! Delays between retries of a failing socket, so a dead socket doesn't turn the server's
+//! accept loops into busy loops.
+
+use std::time::Duration;
+
+/// First delay after a failed accept or receive
+pub const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+pub const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(5);
+/// Consecutive accept or receive failures after which a socket is bound again, e.g. once the
+/// console woke up from sleep with a dead socket
+pub const REBIND_AFTER_FAILURES: u32 = 10;
+pub const MAX_BIND_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+    /// Since the last success
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            next: initial,
+            failures: 0,
+        }
+    }
+
+    /// Records a failure and returns how long to wait before trying again, doubling up to
+    /// the maximum.
+    pub fn fail(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        self.failures = self.failures.saturating_add(1);
+        delay
+    }
+
+    pub fn succeed(&mut self) {
+        self.next = self.initial;
+        self.failures = 0;
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_up_to_the_maximum() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+        assert_eq!(backoff.fail(), Duration::from_millis(100));
+        assert_eq!(backoff.fail(), Duration::from_millis(200));
+        assert_eq!(backoff.fail(), Duration::from_millis(400));
+        assert_eq!(backoff.fail(), Duration::from_millis(500));
+        assert_eq!(backoff.fail(), Duration::from_millis(500));
+        assert_eq!(backoff.failures(), 5);
+    }
+
+    #[test]
+    fn success_starts_over() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(5));
+        backoff.fail();
+        backoff.fail();
+        backoff.succeed();
+        assert_eq!(backoff.failures(), 0);
+        assert_eq!(backoff.fail(), Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,713 @@
+//! Minimal BNTX reader that locates every texture's image data.
+//!
+//! Layout (little endian, offsets relative to the start of the file):
+//!
+//! ```text
+//! 0x00  "BNTX" file header
+//! 0x20  "NX  " container: texture count, pointer to the BRTI pointer array, pointer to BRTD
+//! ....  "BRTI" texture infos: format, dimensions, image size, pointer to the mip offsets
+//! ....  "BRTD" block holding the image data of every texture
+//! ```
+//!
+//! Once the game has loaded a BNTX it relocates the pointers in place, turning file offsets
+//! into addresses. Pass the buffer's address as `base_address` to read such a buffer, or `0`
+//! for a file straight from the SD card.
+
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::Range;
+
+const BNTX_MAGIC: &[u8] = b"BNTX";
+const NX_MAGIC: &[u8] = b"NX  ";
+const BRTI_MAGIC: &[u8] = b"BRTI";
+const BRTD_MAGIC: &[u8] = b"BRTD";
+
+const NX_HEADER_OFFSET: usize = 0x20;
+const BLOCK_HEADER_SIZE: usize = 0x10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BntxError {
+    /// A read at `offset` went past the end of the data
+    OutOfBounds { offset: usize },
+    BadMagic { expected: &'static str, offset: usize },
+    BigEndian,
+    /// A texture's image data does not lie inside the BRTD block
+    DataOutsideBrtd { texture: usize },
+}
+
+impl fmt::Display for BntxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BntxError::OutOfBounds { offset } => write!(f, "read out of bounds at {:#x}", offset),
+            BntxError::BadMagic { expected, offset } => {
+                write!(f, "expected {:?} at {:#x}", expected, offset)
+            }
+            BntxError::BigEndian => write!(f, "big endian BNTX files are not supported"),
+            BntxError::DataOutsideBrtd { texture } => {
+                write!(f, "texture {} has image data outside of the BRTD block", texture)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BntxError {}
+
+/// A property that must be identical for a texture to be refreshed in place.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    Format { loaded: u32, replace: u32 },
+    Width { loaded: u32, replace: u32 },
+    Height { loaded: u32, replace: u32 },
+    MipCount { loaded: u16, replace: u16 },
+    ArrayLayers { loaded: u32, replace: u32 },
+    DataSize { loaded: usize, replace: usize },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Format { loaded, replace } => {
+                write!(f, "format {:#x} (loaded {:#x})", replace, loaded)
+            }
+            Mismatch::Width { loaded, replace } => write!(f, "width {} (loaded {})", replace, loaded),
+            Mismatch::Height { loaded, replace } => {
+                write!(f, "height {} (loaded {})", replace, loaded)
+            }
+            Mismatch::MipCount { loaded, replace } => {
+                write!(f, "mip count {} (loaded {})", replace, loaded)
+            }
+            Mismatch::ArrayLayers { loaded, replace } => {
+                write!(f, "array layers {} (loaded {})", replace, loaded)
+            }
+            Mismatch::DataSize { loaded, replace } => {
+                write!(f, "image size {:#x} (loaded {:#x})", replace, loaded)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture {
+    pub name: Option<String>,
+    pub format: u32,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub mip_count: u16,
+    pub array_layers: u32,
+    /// Offset of the first mip of the first layer, relative to the start of the file
+    pub data_offset: usize,
+    /// Size of the image data, every mip and layer included
+    pub data_size: usize,
+}
+
+impl Texture {
+    pub fn data_range(&self) -> Range<usize> {
+        self.data_offset..self.data_offset.saturating_add(self.data_size)
+    }
+
+    /// Everything that keeps `replace` from being copied over this texture.
+    pub fn compare(&self, replace: &Texture) -> Vec<Mismatch> {
+        let mut mismatches = vec![];
+        if self.format != replace.format {
+            mismatches.push(Mismatch::Format {
+                loaded: self.format,
+                replace: replace.format,
+            });
+        }
+        if self.width != replace.width {
+            mismatches.push(Mismatch::Width {
+                loaded: self.width,
+                replace: replace.width,
+            });
+        }
+        if self.height != replace.height {
+            mismatches.push(Mismatch::Height {
+                loaded: self.height,
+                replace: replace.height,
+            });
+        }
+        if self.mip_count != replace.mip_count {
+            mismatches.push(Mismatch::MipCount {
+                loaded: self.mip_count,
+                replace: replace.mip_count,
+            });
+        }
+        if self.array_layers != replace.array_layers {
+            mismatches.push(Mismatch::ArrayLayers {
+                loaded: self.array_layers,
+                replace: replace.array_layers,
+            });
+        }
+        // Checked last, a size difference usually follows from one of the above
+        if self.data_size != replace.data_size {
+            mismatches.push(Mismatch::DataSize {
+                loaded: self.data_size,
+                replace: replace.data_size,
+            });
+        }
+        mismatches
+    }
+
+    /// Name for log messages, falling back to the texture's position in the file.
+    pub fn display_name(&self, index: usize) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("#{}", index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bntx {
+    pub textures: Vec<Texture>,
+    /// Image data region of the BRTD block
+    pub brtd_data: Range<usize>,
+}
+
+impl Bntx {
+    /// The texture in `self` that corresponds to `replace`, matched by name when both have
+    /// one and by position otherwise.
+    pub fn find_match(&self, index: usize, replace: &Texture) -> Option<&Texture> {
+        match &replace.name {
+            Some(name) => self
+                .textures
+                .iter()
+                .find(|texture| texture.name.as_ref() == Some(name))
+                .or_else(|| self.textures.get(index).filter(|texture| texture.name.is_none())),
+            None => self.textures.get(index),
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    base_address: u64,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8], BntxError> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or(BntxError::OutOfBounds { offset })
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, BntxError> {
+        Ok(u16::from_le_bytes(self.bytes(offset, 2)?.try_into().unwrap()))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, BntxError> {
+        Ok(u32::from_le_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64, BntxError> {
+        Ok(u64::from_le_bytes(self.bytes(offset, 8)?.try_into().unwrap()))
+    }
+
+    /// Reads a pointer field, undoing the game's relocation if needed.
+    /// Pointers past the end of the data are rejected so later arithmetic can't overflow.
+    fn pointer(&self, offset: usize) -> Result<usize, BntxError> {
+        let pointer = self.u64(offset)?;
+        let pointer = if self.base_address != 0 && pointer >= self.base_address {
+            pointer - self.base_address
+        } else {
+            pointer
+        };
+        if pointer > self.data.len() as u64 {
+            return Err(BntxError::OutOfBounds { offset });
+        }
+        Ok(pointer as usize)
+    }
+
+    fn expect_magic(&self, offset: usize, magic: &'static [u8]) -> Result<(), BntxError> {
+        if self.bytes(offset, magic.len())? == magic {
+            Ok(())
+        } else {
+            Err(BntxError::BadMagic {
+                expected: std::str::from_utf8(magic).unwrap(),
+                offset,
+            })
+        }
+    }
+
+    /// Names are stored as a u16 length followed by the string.
+    fn string(&self, offset: usize) -> Option<String> {
+        let len = self.u16(offset).ok()? as usize;
+        let bytes = self.bytes(offset + 2, len).ok()?;
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    fn texture(&self, offset: usize) -> Result<Texture, BntxError> {
+        self.expect_magic(offset, BRTI_MAGIC)?;
+        let info = offset + BLOCK_HEADER_SIZE;
+        let mip_offsets = self.pointer(info + 0x60)?;
+
+        Ok(Texture {
+            name: self.pointer(info + 0x50).ok().and_then(|name| self.string(name)),
+            mip_count: self.u16(info + 0x06)?,
+            format: self.u32(info + 0x0C)?,
+            width: self.u32(info + 0x14)?,
+            height: self.u32(info + 0x18)?,
+            depth: self.u32(info + 0x1C)?,
+            array_layers: self.u32(info + 0x20)?,
+            data_size: self.u32(info + 0x40)? as usize,
+            data_offset: self.pointer(mip_offsets)?,
+        })
+    }
+}
+
+pub fn parse(data: &[u8], base_address: u64) -> Result<Bntx, BntxError> {
+    let reader = Reader { data, base_address };
+
+    reader.expect_magic(0, BNTX_MAGIC)?;
+    if reader.bytes(0x0C, 2)? != [0xFF, 0xFE] {
+        return Err(BntxError::BigEndian);
+    }
+
+    reader.expect_magic(NX_HEADER_OFFSET, NX_MAGIC)?;
+    let texture_count = reader.u32(NX_HEADER_OFFSET + 0x04)? as usize;
+    let info_pointers = reader.pointer(NX_HEADER_OFFSET + 0x08)?;
+    let brtd = reader.pointer(NX_HEADER_OFFSET + 0x10)?;
+
+    reader.expect_magic(brtd, BRTD_MAGIC)?;
+    let brtd_size = reader.u32(brtd + 0x08)? as usize;
+    let brtd_data = brtd.saturating_add(BLOCK_HEADER_SIZE)..brtd.saturating_add(brtd_size);
+    reader.bytes(brtd_data.start, brtd_data.len())?;
+
+    let pointers_size = texture_count
+        .checked_mul(8)
+        .ok_or(BntxError::OutOfBounds { offset: info_pointers })?;
+    reader.bytes(info_pointers, pointers_size)?;
+
+    let mut textures = Vec::with_capacity(texture_count);
+    for i in 0..texture_count {
+        let texture = reader.texture(reader.pointer(info_pointers + i * 8)?)?;
+        let range = texture.data_range();
+        if range.start < brtd_data.start || range.end > brtd_data.end {
+            return Err(BntxError::DataOutsideBrtd { texture: i });
+        }
+        textures.push(texture);
+    }
+
+    Ok(Bntx {
+        textures,
+        brtd_data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_STRIDE: usize = 0xC0;
+    const RELOCATED_BASE: u64 = 0x8_1234_0000;
+
+    type Change = fn(&mut Texture);
+
+    struct Sample {
+        name: Option<&'static str>,
+        format: u32,
+        width: u32,
+        height: u32,
+        mip_count: u16,
+        array_layers: u32,
+        data_size: usize,
+    }
+
+    fn sample(name: Option<&'static str>, data_size: usize) -> Sample {
+        Sample {
+            name,
+            format: 0x1a01,
+            width: 64,
+            height: 32,
+            mip_count: 1,
+            array_layers: 1,
+            data_size,
+        }
+    }
+
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn put_pointer(data: &mut [u8], offset: usize, pointer: usize, base: u64) {
+        put(data, offset, &(pointer as u64 + base).to_le_bytes());
+    }
+
+    /// Lays out a little endian BNTX holding `samples`, with pointers relocated to `base`
+    /// like the game does once the file is loaded.
+    fn build(samples: &[Sample], base: u64) -> Vec<u8> {
+        let info_pointers = 0x40;
+        let first_block = (info_pointers + samples.len() * 8 + 0xF) & !0xF;
+        let brtd = first_block + samples.len() * BLOCK_STRIDE;
+        let brtd_size =
+            BLOCK_HEADER_SIZE + samples.iter().map(|sample| sample.data_size).sum::<usize>();
+        let mut data = vec![0; brtd + brtd_size];
+
+        put(&mut data, 0, BNTX_MAGIC);
+        put(&mut data, 0x0C, &[0xFF, 0xFE]);
+        put(&mut data, NX_HEADER_OFFSET, NX_MAGIC);
+        put(
+            &mut data,
+            NX_HEADER_OFFSET + 0x04,
+            &(samples.len() as u32).to_le_bytes(),
+        );
+        put_pointer(&mut data, NX_HEADER_OFFSET + 0x08, info_pointers, base);
+        put_pointer(&mut data, NX_HEADER_OFFSET + 0x10, brtd, base);
+
+        let mut image_data = brtd + BLOCK_HEADER_SIZE;
+        for (i, sample) in samples.iter().enumerate() {
+            let block = first_block + i * BLOCK_STRIDE;
+            let info = block + BLOCK_HEADER_SIZE;
+            let mip_offsets = block + 0x80;
+            let name = block + 0x90;
+            put_pointer(&mut data, info_pointers + i * 8, block, base);
+            put(&mut data, block, BRTI_MAGIC);
+            put(&mut data, info + 0x06, &sample.mip_count.to_le_bytes());
+            put(&mut data, info + 0x0C, &sample.format.to_le_bytes());
+            put(&mut data, info + 0x14, &sample.width.to_le_bytes());
+            put(&mut data, info + 0x18, &sample.height.to_le_bytes());
+            put(&mut data, info + 0x1C, &1u32.to_le_bytes());
+            put(&mut data, info + 0x20, &sample.array_layers.to_le_bytes());
+            put(
+                &mut data,
+                info + 0x40,
+                &(sample.data_size as u32).to_le_bytes(),
+            );
+            if let Some(text) = sample.name {
+                put_pointer(&mut data, info + 0x50, name, base);
+                put(&mut data, name, &(text.len() as u16).to_le_bytes());
+                put(&mut data, name + 2, text.as_bytes());
+            }
+            put_pointer(&mut data, info + 0x60, mip_offsets, base);
+            put_pointer(&mut data, mip_offsets, image_data, base);
+            image_data += sample.data_size;
+        }
+
+        put(&mut data, brtd, BRTD_MAGIC);
+        put(&mut data, brtd + 0x08, &(brtd_size as u32).to_le_bytes());
+        data
+    }
+
+    fn two_textures() -> Vec<Sample> {
+        vec![sample(Some("body"), 0x200), sample(Some("eye"), 0x80)]
+    }
+
+    fn texture(name: Option<&str>) -> Texture {
+        Texture {
+            name: name.map(str::to_string),
+            format: 0x1a01,
+            width: 64,
+            height: 32,
+            depth: 1,
+            mip_count: 1,
+            array_layers: 1,
+            data_offset: 0x100,
+            data_size: 0x200,
+        }
+    }
+
+    #[test]
+    fn parses_a_file_from_the_sd_card() {
+        let data = build(&two_textures(), 0);
+        let bntx = parse(&data, 0).unwrap();
+
+        let brtd = data.len() - BLOCK_HEADER_SIZE - 0x280;
+        assert_eq!(bntx.brtd_data, brtd + BLOCK_HEADER_SIZE..data.len());
+        assert_eq!(bntx.textures.len(), 2);
+        let body = &bntx.textures[0];
+        assert_eq!(body.name.as_deref(), Some("body"));
+        assert_eq!(
+            (body.format, body.width, body.height, body.depth),
+            (0x1a01, 64, 32, 1)
+        );
+        assert_eq!((body.mip_count, body.array_layers), (1, 1));
+        assert_eq!(
+            body.data_range(),
+            bntx.brtd_data.start..bntx.brtd_data.start + 0x200
+        );
+        assert_eq!(bntx.textures[1].name.as_deref(), Some("eye"));
+        assert_eq!(
+            bntx.textures[1].data_range(),
+            body.data_range().end..data.len()
+        );
+    }
+
+    #[test]
+    fn parses_a_relocated_buffer() {
+        let unrelocated = parse(&build(&two_textures(), 0), 0).unwrap();
+        let relocated = build(&two_textures(), RELOCATED_BASE);
+        assert_eq!(parse(&relocated, RELOCATED_BASE), Ok(unrelocated));
+        // Read as a file, the relocated pointers are far past the end
+        assert_eq!(
+            parse(&relocated, 0),
+            Err(BntxError::OutOfBounds {
+                offset: NX_HEADER_OFFSET + 0x08
+            })
+        );
+    }
+
+    #[test]
+    fn parses_unnamed_textures() {
+        let data = build(&[sample(None, 0x40)], 0);
+        assert_eq!(parse(&data, 0).unwrap().textures[0].name, None);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let data = build(&two_textures(), 0);
+        assert_eq!(
+            parse(&data[..0x10], 0),
+            Err(BntxError::OutOfBounds {
+                offset: NX_HEADER_OFFSET
+            })
+        );
+        // Cuts into the BRTD image data
+        let brtd = data.len() - BLOCK_HEADER_SIZE - 0x280;
+        assert_eq!(
+            parse(&data[..data.len() - 1], 0),
+            Err(BntxError::OutOfBounds {
+                offset: brtd + BLOCK_HEADER_SIZE
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut data = build(&two_textures(), 0);
+        put(&mut data, 0, b"BNTY");
+        assert_eq!(
+            parse(&data, 0),
+            Err(BntxError::BadMagic {
+                expected: "BNTX",
+                offset: 0
+            })
+        );
+
+        let mut data = build(&two_textures(), 0);
+        let first_block = 0x50;
+        put(&mut data, first_block, b"BRTX");
+        assert_eq!(
+            parse(&data, 0),
+            Err(BntxError::BadMagic {
+                expected: "BRTI",
+                offset: first_block
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_big_endian_files() {
+        let mut data = build(&two_textures(), 0);
+        put(&mut data, 0x0C, &[0xFE, 0xFF]);
+        assert_eq!(parse(&data, 0), Err(BntxError::BigEndian));
+    }
+
+    #[test]
+    fn rejects_image_data_outside_brtd() {
+        let mut data = build(&two_textures(), 0);
+        // Point the second texture's first mip at the file header
+        let mip_offsets = 0x50 + BLOCK_STRIDE + 0x80;
+        put_pointer(&mut data, mip_offsets, 0, 0);
+        assert_eq!(
+            parse(&data, 0),
+            Err(BntxError::DataOutsideBrtd { texture: 1 })
+        );
+
+        let mut data = build(&two_textures(), 0);
+        let size = 0x50 + BLOCK_HEADER_SIZE + 0x40;
+        put(&mut data, size, &0x1000u32.to_le_bytes());
+        assert_eq!(
+            parse(&data, 0),
+            Err(BntxError::DataOutsideBrtd { texture: 0 })
+        );
+    }
+
+    #[test]
+    fn identical_textures_match() {
+        assert_eq!(texture(Some("a")).compare(&texture(Some("a"))), vec![]);
+    }
+
+    #[test]
+    fn reports_every_mismatch() {
+        let loaded = texture(None);
+        let cases: Vec<(Change, Mismatch)> = vec![
+            (
+                |texture| texture.format = 0x1a02,
+                Mismatch::Format {
+                    loaded: 0x1a01,
+                    replace: 0x1a02,
+                },
+            ),
+            (
+                |texture| texture.width = 128,
+                Mismatch::Width {
+                    loaded: 64,
+                    replace: 128,
+                },
+            ),
+            (
+                |texture| texture.height = 16,
+                Mismatch::Height {
+                    loaded: 32,
+                    replace: 16,
+                },
+            ),
+            (
+                |texture| texture.mip_count = 7,
+                Mismatch::MipCount {
+                    loaded: 1,
+                    replace: 7,
+                },
+            ),
+            (
+                |texture| texture.array_layers = 6,
+                Mismatch::ArrayLayers {
+                    loaded: 1,
+                    replace: 6,
+                },
+            ),
+            (
+                |texture| texture.data_size = 0x400,
+                Mismatch::DataSize {
+                    loaded: 0x200,
+                    replace: 0x400,
+                },
+            ),
+        ];
+        for (change, mismatch) in cases {
+            let mut replace = texture(None);
+            change(&mut replace);
+            assert_eq!(loaded.compare(&replace), vec![mismatch]);
+        }
+
+        let mut replace = texture(None);
+        replace.width = 128;
+        replace.data_size = 0x400;
+        assert_eq!(
+            loaded.compare(&replace),
+            vec![
+                Mismatch::Width {
+                    loaded: 64,
+                    replace: 128
+                },
+                Mismatch::DataSize {
+                    loaded: 0x200,
+                    replace: 0x400
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_named_textures_by_name() {
+        let loaded = Bntx {
+            textures: vec![texture(Some("body")), texture(Some("eye"))],
+            brtd_data: 0..0,
+        };
+        let found = loaded.find_match(0, &texture(Some("eye"))).unwrap();
+        assert_eq!(found.name.as_deref(), Some("eye"));
+        // A name the loaded file doesn't have never falls back to a named texture
+        assert_eq!(loaded.find_match(0, &texture(Some("hair"))), None);
+    }
+
+    #[test]
+    fn matches_unnamed_textures_by_index() {
+        let loaded = Bntx {
+            textures: vec![texture(Some("body")), texture(None)],
+            brtd_data: 0..0,
+        };
+        assert_eq!(
+            loaded
+                .find_match(0, &texture(None))
+                .unwrap()
+                .name
+                .as_deref(),
+            Some("body")
+        );
+        assert_eq!(
+            loaded.find_match(1, &texture(Some("hair"))),
+            Some(&texture(None))
+        );
+        assert_eq!(loaded.find_match(2, &texture(None)), None);
+    }
+
+    /// Laid out block for block like the game's UI textures, by `testdata/make_sample_bntx.py`.
+    const SAMPLE: &[u8] = include_bytes!("../testdata/sample.bntx");
+
+    fn read_u32(data: &[u8], offset: usize) -> usize {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    /// Adds `base` to every pointer the file's relocation table lists, like the game does when
+    /// it loads the file.
+    fn relocate(file: &[u8], base: u64) -> Vec<u8> {
+        let mut data = file.to_vec();
+        let rlt = read_u32(file, 0x18);
+        assert_eq!(&file[rlt..rlt + 4], b"_RLT");
+        let sections = read_u32(file, rlt + 0x08);
+        let entries = rlt + 0x10 + sections * 0x18;
+        let entry_count: usize = (0..sections)
+            .map(|i| read_u32(file, rlt + 0x10 + i * 0x18 + 0x14))
+            .sum();
+        for entry in (0..entry_count).map(|i| entries + i * 8) {
+            let mut offset = read_u32(file, entry);
+            let structs = u16::from_le_bytes([file[entry + 4], file[entry + 5]]);
+            let (pointers, padding) = (file[entry + 6] as usize, file[entry + 7] as usize);
+            for _ in 0..structs {
+                for _ in 0..pointers {
+                    put_pointer(&mut data, offset, read_u32(file, offset), base);
+                    offset += 8;
+                }
+                offset += padding * 8;
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn parses_the_sample_file() {
+        let bntx = parse(SAMPLE, 0).unwrap();
+        assert_eq!(bntx.brtd_data, 0x1000..0x1D00);
+
+        let names: Vec<_> = bntx
+            .textures
+            .iter()
+            .map(|texture| texture.name.as_deref())
+            .collect();
+        assert_eq!(
+            names,
+            [Some("chara_0_sample_00"), Some("chara_1_sample_00")]
+        );
+        let bc1 = &bntx.textures[0];
+        assert_eq!(
+            (bc1.format, bc1.width, bc1.height, bc1.depth),
+            (0x1a01, 64, 64, 1)
+        );
+        assert_eq!((bc1.mip_count, bc1.array_layers), (1, 1));
+        assert_eq!(bc1.data_range(), 0x1000..0x1800);
+        let rgba = &bntx.textures[1];
+        assert_eq!((rgba.format, rgba.width, rgba.height), (0x0b06, 16, 16));
+        assert_eq!(rgba.mip_count, 2);
+        assert_eq!(rgba.data_range(), 0x1800..0x1D00);
+    }
+
+    #[test]
+    fn parses_the_sample_once_the_game_relocated_it() {
+        let relocated = relocate(SAMPLE, RELOCATED_BASE);
+        assert_ne!(relocated, SAMPLE);
+        assert_eq!(parse(&relocated, RELOCATED_BASE), parse(SAMPLE, 0));
+    }
+
+    #[test]
+    fn the_sample_matches_itself_texture_for_texture() {
+        let bntx = parse(SAMPLE, 0).unwrap();
+        for (i, texture) in bntx.textures.iter().enumerate() {
+            assert_eq!(bntx.find_match(i, texture), Some(texture));
+            assert!(texture.compare(texture).is_empty());
+        }
+        let swapped = bntx.find_match(0, &bntx.textures[1]).unwrap();
+        assert_eq!(swapped.name.as_deref(), Some("chara_1_sample_00"));
+    }
+}
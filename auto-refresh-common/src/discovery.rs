@@ -0,0 +1,154 @@
+//! LAN discovery of running servers.
+//!
+//! The client broadcasts a `DISCOVER <protocol version>` datagram to `DISCOVERY_PORT`, and
+//! every server that hears it answers the sender with a single
+//! `server\t<ip>\t<port>\t<plugin version>\t<nickname>` datagram.
+
+use std::fmt;
+use std::net::IpAddr;
+
+pub const DISCOVERY_PORT: u16 = 7879;
+/// Longest datagram either side sends
+pub const MAX_DATAGRAM: usize = 512;
+
+const DISCOVER: &str = "DISCOVER";
+
+pub fn discover_request() -> String {
+    format!("{} {}", DISCOVER, crate::protocol::PROTOCOL_VERSION)
+}
+
+pub fn is_discover_request(datagram: &[u8]) -> bool {
+    String::from_utf8_lossy(datagram)
+        .split_whitespace()
+        .next()
+        == Some(DISCOVER)
+}
+
+/// A server's answer to a discovery broadcast.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    pub ip: IpAddr,
+    /// Refresh requests go to this TCP port
+    pub port: u16,
+    pub version: String,
+    /// Set in the server's config, to tell consoles apart
+    pub nickname: String,
+}
+
+impl Announcement {
+    pub fn to_line(&self) -> String {
+        format!(
+            "server\t{}\t{}\t{}\t{}",
+            self.ip,
+            self.port,
+            self.version.replace(&['\t', '\r', '\n'][..], " "),
+            self.nickname.replace(&['\t', '\r', '\n'][..], " ")
+        )
+    }
+
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let mut parts = line.trim_end_matches(&['\r', '\n'][..]).splitn(5, '\t');
+        if parts.next()? != "server" {
+            return None;
+        }
+        Some(Self {
+            ip: parts.next()?.parse().ok()?,
+            port: parts.next()?.parse().ok()?,
+            version: parts.next()?.to_string(),
+            nickname: parts.next().unwrap_or("").to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Announcement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}:{}, v{})",
+            self.nickname, self.ip, self.port, self.version
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn announcement(nickname: &str) -> Announcement {
+        Announcement {
+            ip: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)),
+            port: 7878,
+            version: "1.4.0".to_string(),
+            nickname: nickname.to_string(),
+        }
+    }
+
+    #[test]
+    fn discover_requests_are_recognized() {
+        let request = discover_request();
+        assert_eq!(
+            request,
+            format!("DISCOVER {}", crate::protocol::PROTOCOL_VERSION)
+        );
+        assert!(is_discover_request(request.as_bytes()));
+        assert!(is_discover_request(b"DISCOVER"));
+        assert!(is_discover_request(b"  DISCOVER 99\r\n"));
+        assert!(!is_discover_request(b"discover 1"));
+        assert!(!is_discover_request(b"DISCOVERY 1"));
+        assert!(!is_discover_request(b""));
+        assert!(!is_discover_request(
+            b"server\t192.168.1.20\t7878\t1.4.0\tx"
+        ));
+    }
+
+    #[test]
+    fn announcements_round_trip() {
+        let announcement = announcement("Living room");
+        let line = announcement.to_line();
+        assert_eq!(line, "server\t192.168.1.20\t7878\t1.4.0\tLiving room");
+        assert_eq!(
+            Announcement::parse_line(&format!("{}\r\n", line)),
+            Some(announcement.clone())
+        );
+        assert_eq!(
+            announcement.to_string(),
+            "Living room (192.168.1.20:7878, v1.4.0)"
+        );
+    }
+
+    #[test]
+    fn nicknames_stay_on_one_line() {
+        let line = announcement("Living\troom\r\nSwitch").to_line();
+        assert_eq!(
+            Announcement::parse_line(&line).unwrap().nickname,
+            "Living room  Switch"
+        );
+        assert_eq!(
+            Announcement::parse_line("server\t192.168.1.20\t7878\t1.4.0")
+                .unwrap()
+                .nickname,
+            ""
+        );
+    }
+
+    #[test]
+    fn malformed_announcements_are_ignored() {
+        assert_eq!(Announcement::parse_line("DISCOVER 1"), None);
+        assert_eq!(
+            Announcement::parse_line("server\tconsole\t7878\t1.4.0\tx"),
+            None
+        );
+        assert_eq!(
+            Announcement::parse_line("server\t192.168.1.20\t78780\t1.4.0\tx"),
+            None
+        );
+        assert_eq!(Announcement::parse_line("server\t192.168.1.20\t7878"), None);
+        assert_eq!(
+            Announcement::parse_line("server\t::1\t7878\t1.4.0\tx")
+                .unwrap()
+                .ip,
+            "::1".parse::<IpAddr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,17 @@
+//! Code shared between auto-refresh-server and auto-refresh-client, along with the server
+//! logic that has to be testable off the console.
+//! Kept free of dependencies so it builds for both the switch and the host.
+
+pub mod arc_path;
+pub mod backoff;
+pub mod bntx;
+pub mod discovery;
+pub mod log;
+pub mod overlay;
+pub mod pending;
+pub mod poll;
+pub mod protocol;
+pub mod signature;
+pub mod snapshot;
+pub mod trace;
+pub mod tracker;
@@ -0,0 +1,296 @@
+//! Structured log records, shared so the client can parse what the server streams to it.
+//!
+//! Each record travels as one line, `log\t<seq>\t<milliseconds>\t<level>\t<category>\t<message>`,
+//! with tabs and line breaks in the message replaced by spaces.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Records kept in memory for clients that subscribe later.
+pub const RING_CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub const ALL: [Level; 4] = [Level::Debug, Level::Info, Level::Warn, Level::Error];
+
+    pub fn to_token(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+
+    pub fn parse_token(token: &str) -> Option<Self> {
+        match token.to_ascii_lowercase().as_str() {
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" | "warning" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// Connections and requests
+    Network,
+    /// Refreshing, restoring, deferring and dumping files, and the file index
+    Refresh,
+    /// BNTX textures and the threaded load hooks
+    Bntx,
+    /// Signature scanning and game version checks
+    Offsets,
+    /// Reading the config file
+    Config,
+}
+
+impl Category {
+    pub const ALL: [Category; 5] = [
+        Category::Network,
+        Category::Refresh,
+        Category::Bntx,
+        Category::Offsets,
+        Category::Config,
+    ];
+
+    pub fn to_token(self) -> &'static str {
+        match self {
+            Category::Network => "network",
+            Category::Refresh => "refresh",
+            Category::Bntx => "bntx",
+            Category::Offsets => "offsets",
+            Category::Config => "config",
+        }
+    }
+
+    pub fn parse_token(token: &str) -> Option<Self> {
+        match token {
+            "network" => Some(Category::Network),
+            "refresh" => Some(Category::Refresh),
+            "bntx" => Some(Category::Bntx),
+            "offsets" => Some(Category::Offsets),
+            "config" => Some(Category::Config),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    /// Increases by one for every record the server writes
+    pub seq: u64,
+    /// Since the server started
+    pub time_ms: u64,
+    pub level: Level,
+    pub category: Category,
+    pub message: String,
+}
+
+impl LogRecord {
+    pub fn to_line(&self) -> String {
+        format!(
+            "log\t{}\t{}\t{}\t{}\t{}",
+            self.seq,
+            self.time_ms,
+            self.level.to_token(),
+            self.category.to_token(),
+            self.message.replace(&['\t', '\r', '\n'][..], " ")
+        )
+    }
+
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let mut parts = line.trim_end_matches(&['\r', '\n'][..]).splitn(6, '\t');
+        if parts.next()? != "log" {
+            return None;
+        }
+        Some(Self {
+            seq: parts.next()?.parse().ok()?,
+            time_ms: parts.next()?.parse().ok()?,
+            level: Level::parse_token(parts.next()?)?,
+            category: Category::parse_token(parts.next()?)?,
+            message: parts.next().unwrap_or("").to_string(),
+        })
+    }
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:>5}.{:03}] {:<5} {}: {}",
+            self.time_ms / 1000,
+            self.time_ms % 1000,
+            self.level.to_token().to_ascii_uppercase(),
+            self.category.to_token(),
+            self.message
+        )
+    }
+}
+
+/// The most recent records, oldest first.
+#[derive(Debug)]
+pub struct RingBuffer {
+    records: VecDeque<LogRecord>,
+    capacity: usize,
+    next_seq: u64,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+            next_seq: 0,
+        }
+    }
+
+    /// Stores a record, numbering it and dropping the oldest one if full.
+    pub fn push(
+        &mut self,
+        time_ms: u64,
+        level: Level,
+        category: Category,
+        message: String,
+    ) -> LogRecord {
+        let record = LogRecord {
+            seq: self.next_seq,
+            time_ms,
+            level,
+            category,
+            message,
+        };
+        self.next_seq += 1;
+        if self.records.len() >= self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record.clone());
+        record
+    }
+
+    /// Stored records at or above `level`.
+    pub fn records(&self, level: Level) -> Vec<LogRecord> {
+        self.records
+            .iter()
+            .filter(|record| record.level >= level)
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(message: &str) -> LogRecord {
+        LogRecord {
+            seq: 7,
+            time_ms: 61_005,
+            level: Level::Warn,
+            category: Category::Bntx,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn ring_keeps_the_latest_records() {
+        let mut ring = RingBuffer::new(2);
+        for message in &["a", "b", "c"] {
+            ring.push(0, Level::Info, Category::Refresh, message.to_string());
+        }
+        let records = ring.records(Level::Debug);
+        let messages: Vec<_> = records
+            .iter()
+            .map(|record| record.message.as_str())
+            .collect();
+        assert_eq!(messages, ["b", "c"]);
+        let seqs: Vec<_> = records.iter().map(|record| record.seq).collect();
+        assert_eq!(seqs, [1, 2]);
+        assert_eq!(ring.len(), 2);
+    }
+
+    #[test]
+    fn ring_filters_by_level() {
+        let mut ring = RingBuffer::new(RING_CAPACITY);
+        assert!(ring.is_empty());
+        for level in &Level::ALL {
+            ring.push(0, *level, Category::Network, level.to_token().to_string());
+        }
+        let warnings: Vec<_> = ring
+            .records(Level::Warn)
+            .into_iter()
+            .map(|record| record.level)
+            .collect();
+        assert_eq!(warnings, [Level::Warn, Level::Error]);
+        assert_eq!(ring.records(Level::Debug).len(), 4);
+    }
+
+    #[test]
+    fn levels_and_categories_round_trip() {
+        for level in &Level::ALL {
+            assert_eq!(Level::parse_token(level.to_token()), Some(*level));
+        }
+        assert_eq!(Level::parse_token("WARNING"), Some(Level::Warn));
+        assert_eq!(Level::parse_token("Error"), Some(Level::Error));
+        assert_eq!(Level::parse_token("trace"), None);
+        assert!(Level::Debug < Level::Info && Level::Warn < Level::Error);
+
+        for category in &Category::ALL {
+            assert_eq!(Category::parse_token(category.to_token()), Some(*category));
+        }
+        assert_eq!(Category::parse_token("gui"), None);
+    }
+
+    #[test]
+    fn records_travel_as_one_line() {
+        let record = record("texture body\twas\r\nrejected");
+        let line = record.to_line();
+        assert_eq!(
+            line,
+            "log\t7\t61005\twarn\tbntx\ttexture body was  rejected"
+        );
+        let parsed = LogRecord::parse_line(&format!("{}\r\n", line)).unwrap();
+        assert_eq!(parsed.message, "texture body was  rejected");
+        assert_eq!(
+            parsed,
+            LogRecord {
+                message: parsed.message.clone(),
+                ..record
+            }
+        );
+
+        assert_eq!(
+            LogRecord::parse_line("log\t1\t2\tinfo\trefresh")
+                .unwrap()
+                .message,
+            ""
+        );
+        assert_eq!(LogRecord::parse_line("log\t1\t2\tloud\trefresh\tx"), None);
+        assert_eq!(LogRecord::parse_line("ui/a.bntx\tRefreshed"), None);
+    }
+
+    #[test]
+    fn records_display_with_their_time() {
+        assert_eq!(
+            record("texture body was rejected").to_string(),
+            "[   61.005] WARN  bntx: texture body was rejected"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Files pushed into the server's memory instead of onto the SD card. The server serves them
+//! the next time the game loads their ARC path, at whatever size they are, until they are
+//! replaced, a regular refresh of the path drops them or the game restarts.
+
+use std::collections::HashMap;
+
+pub const DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlayFile {
+    pub path: String,
+    pub data: Vec<u8>,
+    /// The bytes were written to the SD card since they were pushed
+    pub persisted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Inserted {
+    New,
+    /// The path was already overlaid, its bytes were swapped for the new ones
+    Replaced,
+    /// Keeping the bytes would go over the budget, the overlay is unchanged
+    OverBudget,
+}
+
+#[derive(Debug)]
+pub struct OverlayStore {
+    entries: HashMap<u64, OverlayFile>,
+    budget: usize,
+    used: usize,
+}
+
+impl OverlayStore {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            budget,
+            used: 0,
+        }
+    }
+
+    pub fn insert(&mut self, hash: u64, path: &str, data: Vec<u8>) -> Inserted {
+        let replaced = self.entries.get(&hash).map_or(0, |file| file.data.len());
+        if self.used - replaced + data.len() > self.budget {
+            return Inserted::OverBudget;
+        }
+        self.used = self.used - replaced + data.len();
+        let file = OverlayFile {
+            path: path.to_string(),
+            data,
+            persisted: false,
+        };
+        match self.entries.insert(hash, file) {
+            Some(_) => Inserted::Replaced,
+            None => Inserted::New,
+        }
+    }
+
+    pub fn get(&self, hash: u64) -> Option<&OverlayFile> {
+        self.entries.get(&hash)
+    }
+
+    /// Drops the overlaid bytes of `hash`, so the game loads the file from the SD card again.
+    pub fn remove(&mut self, hash: u64) -> Option<OverlayFile> {
+        let file = self.entries.remove(&hash)?;
+        self.used -= file.data.len();
+        Some(file)
+    }
+
+    pub fn mark_persisted(&mut self, hash: u64) {
+        if let Some(file) = self.entries.get_mut(&hash) {
+            file.persisted = true;
+        }
+    }
+
+    /// Every overlaid hash, sorted by path.
+    pub fn hashes(&self) -> Vec<u64> {
+        let mut hashes: Vec<(&str, u64)> = self
+            .entries
+            .iter()
+            .map(|(hash, file)| (file.path.as_str(), *hash))
+            .collect();
+        hashes.sort();
+        hashes.into_iter().map(|(_, hash)| hash).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Overlaid files whose current bytes were written to the SD card.
+    pub fn persisted_count(&self) -> usize {
+        self.entries.values().filter(|file| file.persisted).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Bytes held by the overlay.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserts_stay_within_the_budget() {
+        let mut store = OverlayStore::new(8);
+        assert_eq!(store.insert(1, "a", vec![0; 5]), Inserted::New);
+        assert_eq!(store.insert(2, "b", vec![0; 4]), Inserted::OverBudget);
+        assert!(store.get(2).is_none());
+        assert_eq!(store.insert(1, "a", vec![0; 8]), Inserted::Replaced);
+        assert_eq!(store.used(), 8);
+        assert_eq!(store.remove(1).unwrap().data.len(), 8);
+        assert_eq!(store.used(), 0);
+        assert!(store.remove(1).is_none());
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn new_bytes_are_not_persisted() {
+        let mut store = OverlayStore::new(DEFAULT_BUDGET);
+        store.insert(1, "a", vec![1]);
+        store.insert(2, "b", vec![2]);
+        store.mark_persisted(1);
+        assert!(store.get(1).unwrap().persisted);
+        assert_eq!(store.persisted_count(), 1);
+        store.insert(1, "a", vec![3]);
+        assert_eq!(store.persisted_count(), 0);
+    }
+
+    #[test]
+    fn hashes_are_sorted_by_path() {
+        let mut store = OverlayStore::new(DEFAULT_BUDGET);
+        store.insert(1, "ui/b", vec![]);
+        store.insert(2, "fighter/a", vec![]);
+        store.insert(3, "ui/a", vec![]);
+        assert_eq!(store.hashes(), [2, 3, 1]);
+    }
+}
@@ -0,0 +1,228 @@
+//! Refreshes pushed for files the game hasn't loaded yet.
+//!
+//! The server keeps the bytes of a pushed BNTX until its load hook sees the file come in, then
+//! replaces the textures of the fresh buffer with them.
+//!
+//! Outcomes are numbered as they resolve and kept for a while, so every client polling
+//! `PENDING` can read them with its own cursor instead of the first one taking them all.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::protocol::FileResult;
+
+pub const DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+/// How many resolved refreshes are kept for clients to read.
+pub const MAX_RESOLVED: usize = 256;
+/// How long a resolved refresh is kept for clients to read.
+pub const RESOLVED_LIFETIME_MS: u64 = 10 * 60 * 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingRefresh {
+    pub path: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Deferred {
+    New,
+    /// The file was already waiting, its bytes were swapped for the new ones
+    Replaced,
+    /// Keeping the bytes would go over the budget, the table is unchanged
+    OverBudget,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Resolved {
+    seq: u64,
+    /// Milliseconds since the server started
+    resolved_at: u64,
+    result: FileResult,
+}
+
+#[derive(Debug)]
+pub struct PendingTable {
+    entries: HashMap<u64, PendingRefresh>,
+    budget: usize,
+    used: usize,
+    /// Oldest first
+    resolved: VecDeque<Resolved>,
+    next_seq: u64,
+}
+
+impl PendingTable {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            budget,
+            used: 0,
+            resolved: VecDeque::new(),
+            next_seq: 0,
+        }
+    }
+
+    /// Records a refresh for `hash`, replacing any earlier one for the same file.
+    pub fn insert(&mut self, hash: u64, path: String, data: Vec<u8>) -> Deferred {
+        let replaced = self.entries.get(&hash).map_or(0, |pending| pending.data.len());
+        if self.used - replaced + data.len() > self.budget {
+            return Deferred::OverBudget;
+        }
+        self.used = self.used - replaced + data.len();
+        match self.entries.insert(hash, PendingRefresh { path, data }) {
+            Some(_) => Deferred::Replaced,
+            None => Deferred::New,
+        }
+    }
+
+    pub fn contains(&self, hash: u64) -> bool {
+        self.entries.contains_key(&hash)
+    }
+
+    /// Removes the refresh waiting for `hash`, to resolve it against the loaded file.
+    pub fn take(&mut self, hash: u64) -> Option<PendingRefresh> {
+        let pending = self.entries.remove(&hash)?;
+        self.used -= pending.data.len();
+        Some(pending)
+    }
+
+    /// Keeps the outcome of a taken refresh for clients to read. `now` is in milliseconds
+    /// since the server started.
+    pub fn record_resolved(&mut self, result: FileResult, now: u64) {
+        self.expire(now);
+        if self.resolved.len() >= MAX_RESOLVED {
+            self.resolved.pop_front();
+        }
+        self.resolved.push_back(Resolved {
+            seq: self.next_seq,
+            resolved_at: now,
+            result,
+        });
+        self.next_seq += 1;
+    }
+
+    /// Outcomes resolved at or after `cursor`, or every one still kept without a cursor.
+    pub fn resolved_since(&mut self, cursor: Option<u64>, now: u64) -> Vec<FileResult> {
+        self.expire(now);
+        let cursor = cursor.unwrap_or(0);
+        self.resolved
+            .iter()
+            .filter(|resolved| resolved.seq >= cursor)
+            .map(|resolved| resolved.result.clone())
+            .collect()
+    }
+
+    /// Cursor a client passes next time to read only what resolves after this call.
+    pub fn cursor(&self) -> u64 {
+        self.next_seq
+    }
+
+    fn expire(&mut self, now: u64) {
+        while let Some(oldest) = self.resolved.front() {
+            if now.saturating_sub(oldest.resolved_at) < RESOLVED_LIFETIME_MS {
+                break;
+            }
+            self.resolved.pop_front();
+        }
+    }
+
+    /// Refreshes still waiting, sorted by path.
+    pub fn waiting(&self) -> Vec<FileResult> {
+        let mut waiting: Vec<FileResult> = self
+            .entries
+            .values()
+            .map(|pending| {
+                FileResult::pending(pending.path.as_str(), "waiting for the game to load it")
+            })
+            .collect();
+        waiting.sort_by(|a, b| a.path.cmp(&b.path));
+        waiting
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Bytes held by the waiting refreshes.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve_now(table: &mut PendingTable, hash: u64, now: u64) {
+        let pending = table.take(hash).unwrap();
+        table.record_resolved(FileResult::refreshed(pending.path.as_str()), now);
+    }
+
+    fn paths(results: &[FileResult]) -> Vec<&str> {
+        results.iter().map(|result| result.path.as_str()).collect()
+    }
+
+    #[test]
+    fn inserts_stay_within_the_budget() {
+        let mut table = PendingTable::new(8);
+        assert_eq!(table.insert(1, "a".to_string(), vec![0; 5]), Deferred::New);
+        assert_eq!(
+            table.insert(2, "b".to_string(), vec![0; 4]),
+            Deferred::OverBudget
+        );
+        assert!(!table.contains(2));
+        assert_eq!(table.insert(1, "a".to_string(), vec![0; 8]), Deferred::Replaced);
+        assert_eq!(table.used(), 8);
+        assert_eq!(table.take(1).unwrap().data.len(), 8);
+        assert_eq!(table.used(), 0);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn every_client_reads_the_resolved_outcomes() {
+        let mut table = PendingTable::new(DEFAULT_BUDGET);
+        table.insert(1, "a".to_string(), vec![1]);
+        table.insert(2, "b".to_string(), vec![2]);
+        resolve_now(&mut table, 1, 0);
+
+        let first_cursor = table.cursor();
+        assert_eq!(paths(&table.resolved_since(None, 0)), ["a"]);
+        assert_eq!(paths(&table.resolved_since(None, 0)), ["a"]);
+
+        resolve_now(&mut table, 2, 0);
+        assert_eq!(paths(&table.resolved_since(Some(first_cursor), 0)), ["b"]);
+        assert_eq!(paths(&table.resolved_since(None, 0)), ["a", "b"]);
+        assert!(table.resolved_since(Some(table.cursor()), 0).is_empty());
+    }
+
+    #[test]
+    fn resolved_outcomes_expire() {
+        let mut table = PendingTable::new(DEFAULT_BUDGET);
+        table.insert(1, "a".to_string(), vec![1]);
+        table.insert(2, "b".to_string(), vec![2]);
+        resolve_now(&mut table, 1, 0);
+        resolve_now(&mut table, 2, 1000);
+
+        let read = table.resolved_since(None, RESOLVED_LIFETIME_MS);
+        assert_eq!(paths(&read), ["b"]);
+        assert!(table.resolved_since(None, RESOLVED_LIFETIME_MS + 1000).is_empty());
+    }
+
+    #[test]
+    fn only_the_latest_resolved_outcomes_are_kept() {
+        let mut table = PendingTable::new(DEFAULT_BUDGET);
+        for hash in 0..MAX_RESOLVED as u64 + 1 {
+            table.insert(hash, hash.to_string(), vec![]);
+            resolve_now(&mut table, hash, 0);
+        }
+        let resolved = table.resolved_since(None, 0);
+        assert_eq!(resolved.len(), MAX_RESOLVED);
+        assert_eq!(resolved[0].path, "1");
+    }
+}
@@ -0,0 +1,215 @@
+//! Change detection for the server's SD polling mode, which refreshes files changed on the SD
+//! card without a client watching them.
+//!
+//! A change is only reported once the file kept the same size and modification time for a
+//! whole poll, so a file still being copied over FTP isn't refreshed half written.
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Observed {
+    Unchanged,
+    /// Changed since the last poll, checked again on the next one
+    Settling,
+    Changed,
+}
+
+#[derive(Debug, Default)]
+pub struct Poller {
+    /// Stamp each changed file had on the previous poll
+    settling: HashMap<String, Stamp>,
+}
+
+impl Poller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compares the stamp `path` has now with the one it was indexed with.
+    pub fn observe(&mut self, path: &str, indexed: Stamp, current: Stamp) -> Observed {
+        if current == indexed {
+            self.settling.remove(path);
+            return Observed::Unchanged;
+        }
+        match self.settling.insert(path.to_string(), current) {
+            Some(previous) if previous == current => {
+                self.settling.remove(path);
+                Observed::Changed
+            }
+            _ => Observed::Settling,
+        }
+    }
+
+    /// Drops the files that are no longer indexed.
+    pub fn retain<F: Fn(&str) -> bool>(&mut self, is_indexed: F) {
+        self.settling.retain(|path, _| is_indexed(path));
+    }
+}
+
+/// Whether `path` matches `pattern`, where `*` stands for any run of characters, `/` included,
+/// and `?` for exactly one.
+pub fn matches_pattern(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    let (mut p, mut s) = (0, 0);
+    // Where the last `*` was, and the path position it's currently matched up to
+    let mut backtrack = None;
+    while s < path.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, s));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == path[s] => {
+                p += 1;
+                s += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    s = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+pub fn is_excluded(patterns: &[String], path: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| matches_pattern(pattern, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn stamp(size: u64, secs: u64) -> Stamp {
+        Stamp {
+            size,
+            modified: Some(UNIX_EPOCH + Duration::from_secs(secs)),
+        }
+    }
+
+    #[test]
+    fn stars_match_any_run_of_characters() {
+        assert!(matches_pattern("ui/*", "ui/replace/chara/a.bntx"));
+        assert!(matches_pattern("*.bntx", "ui/a.bntx"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("ui/**", "ui/"));
+        assert!(!matches_pattern("ui/*", "fighter/ui/a"));
+        assert!(!matches_pattern("*.bntx", "ui/a.nutexb"));
+    }
+
+    #[test]
+    fn question_marks_match_one_character() {
+        assert!(matches_pattern("chara_?_*", "chara_0_mario_00.bntx"));
+        assert!(matches_pattern("a?c", "a/c"));
+        assert!(!matches_pattern("a?c", "ac"));
+        assert!(!matches_pattern("a?", "abc"));
+    }
+
+    #[test]
+    fn trailing_stars_match_nothing() {
+        assert!(matches_pattern("ui/a.bntx*", "ui/a.bntx"));
+        assert!(matches_pattern("ui/a.bntx**", "ui/a.bntx"));
+        assert!(!matches_pattern("ui/a.bntx*?", "ui/a.bntx"));
+    }
+
+    #[test]
+    fn stars_backtrack_past_early_matches() {
+        assert!(matches_pattern("*a.bntx", "ui/a.bntx/a.bntx"));
+        assert!(matches_pattern(
+            "*/chara_*_00.bntx",
+            "ui/chara/chara_0_mario_00.bntx"
+        ));
+        assert!(matches_pattern("a*b*c", "aXbXbXc"));
+        assert!(!matches_pattern("a*b*c", "aXbXbX"));
+        assert!(!matches_pattern("*a.bntx", "ui/a.bntx.bak"));
+    }
+
+    #[test]
+    fn changes_are_reported_once_settled() {
+        let mut poller = Poller::new();
+        let indexed = stamp(10, 0);
+        assert_eq!(poller.observe("a", indexed, indexed), Observed::Unchanged);
+
+        // Still being written: the size grows between polls
+        assert_eq!(
+            poller.observe("a", indexed, stamp(20, 1)),
+            Observed::Settling
+        );
+        assert_eq!(
+            poller.observe("a", indexed, stamp(30, 2)),
+            Observed::Settling
+        );
+        assert_eq!(
+            poller.observe("a", indexed, stamp(30, 2)),
+            Observed::Changed
+        );
+        // Settling starts over for the next change
+        assert_eq!(
+            poller.observe("a", indexed, stamp(30, 2)),
+            Observed::Settling
+        );
+    }
+
+    #[test]
+    fn reverted_files_stop_settling() {
+        let mut poller = Poller::new();
+        let indexed = stamp(10, 0);
+        assert_eq!(
+            poller.observe("a", indexed, stamp(20, 1)),
+            Observed::Settling
+        );
+        assert_eq!(poller.observe("a", indexed, indexed), Observed::Unchanged);
+        assert_eq!(
+            poller.observe("a", indexed, stamp(20, 1)),
+            Observed::Settling
+        );
+    }
+
+    #[test]
+    fn removed_files_are_forgotten() {
+        let mut poller = Poller::new();
+        let indexed = stamp(10, 0);
+        assert_eq!(
+            poller.observe("a", indexed, stamp(20, 1)),
+            Observed::Settling
+        );
+        assert_eq!(
+            poller.observe("b", indexed, stamp(20, 1)),
+            Observed::Settling
+        );
+
+        poller.retain(|path| path != "a");
+        assert_eq!(
+            poller.observe("a", indexed, stamp(20, 1)),
+            Observed::Settling
+        );
+        assert_eq!(
+            poller.observe("b", indexed, stamp(20, 1)),
+            Observed::Changed
+        );
+    }
+
+    #[test]
+    fn exclusions_match_any_pattern() {
+        let patterns = vec!["*.bak".to_string(), "ui/temp/*".to_string()];
+        assert!(is_excluded(&patterns, "ui/a.bntx.bak"));
+        assert!(is_excluded(&patterns, "ui/temp/a.bntx"));
+        assert!(!is_excluded(&patterns, "ui/a.bntx"));
+        assert!(!is_excluded(&[], "ui/a.bntx"));
+    }
+}
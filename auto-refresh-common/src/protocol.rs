@@ -0,0 +1,1109 @@
+//! Wire format spoken between the client and the server.
+//!
+//! A request is UTF-8 text: the first line names the command, the following lines are its
+//! arguments, and an empty line ends it. The server answers with one line per result before
+//! closing the connection.
+//! Requests made only of ARC paths (the original format) are treated as a `REFRESH` of the
+//! first line's path, which also ends them.
+//!
+//! A request may open with a `HELLO <protocol version> [token]` line. The server answers it with
+//! its own `HELLO` line, or with a single `DENIED <reason>` line if the token is wrong.
+//!
+//! `RESCAN` answers with a count line, then a `conflict\t<path>\t<used root>\t<ignored root>`
+//! line for every file provided by more than one mod folder.
+//!
+//! `PENDING` may carry a cursor line. Its response ends with a `cursor\t<n>` line, the cursor
+//! to send next time to only get the outcomes resolved since.
+//!
+//! `PING` is answered with a single `PONG` line and does no other work, for heartbeats.
+//!
+//! `LOG_SUBSCRIBE` keeps the connection open: the server sends its stored log records, then
+//! every new one as it is written, until the client disconnects.
+//!
+//! `DUMP` is the one binary response: a `dump\t<path>\t<size>` line followed by exactly `size`
+//! raw bytes, or a `skipped` result line if the file can't be read.
+//!
+//! `OVERLAY` is the one binary request: the `OVERLAY` line, a `<path>\t<size>` line, then
+//! exactly `size` raw bytes.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::log::Level;
+
+pub const DEFAULT_PORT: u16 = 7878;
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const HELLO: &str = "HELLO";
+const DENIED: &str = "DENIED";
+const REFRESH: &str = "REFRESH";
+const REFRESH_ALL: &str = "REFRESH_ALL";
+const RESCAN: &str = "RESCAN";
+const STATUS: &str = "STATUS";
+const PENDING: &str = "PENDING";
+const RESTORE: &str = "RESTORE";
+const RESTORE_ALL: &str = "RESTORE_ALL";
+const DUMP: &str = "DUMP";
+const TRACE_START: &str = "TRACE_START";
+const TRACE_STOP: &str = "TRACE_STOP";
+const LOG_SUBSCRIBE: &str = "LOG_SUBSCRIBE";
+const OVERLAY: &str = "OVERLAY";
+const PERSIST: &str = "PERSIST";
+const PING: &str = "PING";
+pub const PONG: &str = "PONG";
+
+/// Commands made of text lines, ended by an empty line
+const TEXT_COMMANDS: &[&str] = &[
+    REFRESH,
+    REFRESH_ALL,
+    RESCAN,
+    STATUS,
+    PENDING,
+    RESTORE,
+    RESTORE_ALL,
+    DUMP,
+    TRACE_START,
+    TRACE_STOP,
+    LOG_SUBSCRIBE,
+    PERSIST,
+    PING,
+];
+
+const PARTIAL: &str = "partial";
+const MISMATCH: &str = "mismatch";
+const UNSUPPORTED: &str = "unsupported";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Refresh the listed ARC paths
+    Refresh(Vec<String>),
+    /// Refresh every file in the mod folders
+    RefreshAll,
+    /// Rebuild the server's file index from the SD card
+    Rescan,
+    /// Describe the server's state, for diagnostics
+    Status,
+    /// List refreshes waiting for their file to load, and the outcome of those resolved since
+    /// the cursor, or of every one the server still keeps without it
+    Pending(Option<u64>),
+    /// Write back the contents the listed ARC paths had before they were first refreshed
+    Restore(Vec<String>),
+    /// Write back every file the server still has a snapshot of
+    RestoreAll,
+    /// Send back the bytes the game has loaded for an ARC path
+    Dump(String),
+    /// Start recording every file the game loads, discarding any earlier trace
+    TraceStart,
+    /// Stop recording and send back the trace, as described in `trace`
+    TraceStop,
+    /// Stream log records at or above the level, as described in `log`
+    LogSubscribe(Level),
+    /// Keep the bytes following the request in memory and serve them the next time the game
+    /// loads the ARC path
+    Overlay { path: String, size: usize },
+    /// Write the listed overlaid ARC paths to the SD card, or every one if none are listed
+    Persist(Vec<String>),
+    /// Answer `PONG`, to check that the server is up
+    Ping,
+}
+
+impl Command {
+    pub fn parse(data: &str) -> Self {
+        let mut lines = data
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty());
+
+        match lines.clone().next() {
+            Some(REFRESH_ALL) => Command::RefreshAll,
+            Some(RESCAN) => Command::Rescan,
+            Some(STATUS) => Command::Status,
+            Some(PENDING) => Command::Pending(lines.nth(1).and_then(|cursor| cursor.parse().ok())),
+            Some(RESTORE_ALL) => Command::RestoreAll,
+            Some(RESTORE) => Command::Restore(lines.skip(1).map(String::from).collect()),
+            Some(TRACE_START) => Command::TraceStart,
+            Some(TRACE_STOP) => Command::TraceStop,
+            Some(LOG_SUBSCRIBE) => Command::LogSubscribe(
+                lines
+                    .nth(1)
+                    .and_then(Level::parse_token)
+                    .unwrap_or(Level::Info),
+            ),
+            Some(DUMP) => Command::Dump(lines.nth(1).unwrap_or("").to_string()),
+            Some(OVERLAY) => {
+                let mut parts = lines.nth(1).unwrap_or("").splitn(2, '\t');
+                Command::Overlay {
+                    path: parts.next().unwrap_or("").to_string(),
+                    size: parts.next().and_then(|size| size.parse().ok()).unwrap_or(0),
+                }
+            }
+            Some(PERSIST) => Command::Persist(lines.skip(1).map(String::from).collect()),
+            Some(PING) => Command::Ping,
+            Some(REFRESH) => Command::Refresh(lines.skip(1).map(String::from).collect()),
+            _ => Command::Refresh(lines.map(String::from).collect()),
+        }
+    }
+
+    /// The command's keyword, for logging.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Refresh(_) => REFRESH,
+            Command::RefreshAll => REFRESH_ALL,
+            Command::Rescan => RESCAN,
+            Command::Status => STATUS,
+            Command::Pending(_) => PENDING,
+            Command::Restore(_) => RESTORE,
+            Command::RestoreAll => RESTORE_ALL,
+            Command::Dump(_) => DUMP,
+            Command::TraceStart => TRACE_START,
+            Command::TraceStop => TRACE_STOP,
+            Command::LogSubscribe(_) => LOG_SUBSCRIBE,
+            Command::Overlay { .. } => OVERLAY,
+            Command::Persist(_) => PERSIST,
+            Command::Ping => PING,
+        }
+    }
+
+    pub fn to_request(&self) -> String {
+        match self {
+            Command::Refresh(paths) => path_request(REFRESH, paths),
+            Command::RefreshAll => format!("{}\n", REFRESH_ALL),
+            Command::Rescan => format!("{}\n", RESCAN),
+            Command::Status => format!("{}\n", STATUS),
+            Command::Pending(None) => format!("{}\n", PENDING),
+            Command::Pending(Some(cursor)) => format!("{}\n{}\n", PENDING, cursor),
+            Command::Restore(paths) => path_request(RESTORE, paths),
+            Command::RestoreAll => format!("{}\n", RESTORE_ALL),
+            Command::Dump(path) => path_request(DUMP, std::slice::from_ref(path)),
+            Command::TraceStart => format!("{}\n", TRACE_START),
+            Command::TraceStop => format!("{}\n", TRACE_STOP),
+            Command::LogSubscribe(level) => format!("{}\n{}\n", LOG_SUBSCRIBE, level.to_token()),
+            Command::Overlay { path, size } => {
+                format!("{}\n{}\t{}\n", OVERLAY, path.replace("\\", "/"), size)
+            }
+            Command::Persist(paths) => path_request(PERSIST, paths),
+            Command::Ping => format!("{}\n", PING),
+        }
+    }
+}
+
+fn path_request(command: &str, paths: &[String]) -> String {
+    let mut request = format!("{}\n", command);
+    for path in paths {
+        request.push_str(&path.replace("\\", "/"));
+        request.push('\n');
+    }
+    request
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hello {
+    pub version: u32,
+    pub token: Option<String>,
+}
+
+impl Hello {
+    pub fn new(token: Option<String>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            token,
+        }
+    }
+
+    /// The token is the rest of the line, so it may contain spaces.
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let mut parts = line.trim().splitn(3, ' ');
+        if parts.next()? != HELLO {
+            return None;
+        }
+        Some(Self {
+            version: parts.next()?.parse().ok()?,
+            token: parts
+                .next()
+                .map(str::trim)
+                .filter(|token| !token.is_empty())
+                .map(String::from),
+        })
+    }
+
+    pub fn to_line(&self) -> String {
+        match &self.token {
+            Some(token) => format!("{} {} {}", HELLO, self.version, token),
+            None => format!("{} {}", HELLO, self.version),
+        }
+    }
+}
+
+/// Compares every byte whatever the first difference, so the time taken doesn't tell a client
+/// how much of a guessed token was right.
+pub fn token_matches(expected: &str, token: &str) -> bool {
+    let (expected, token) = (expected.as_bytes(), token.as_bytes());
+    let mut difference = expected.len() ^ token.len();
+    for (i, byte) in expected.iter().enumerate() {
+        difference |= (byte ^ token.get(i).unwrap_or(&0)) as usize;
+    }
+    difference == 0
+}
+
+/// How well the server's offsets are known to fit the running game.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameSupport {
+    /// The game version is known and the signature scan agrees with its offset table for every
+    /// symbol
+    Verified,
+    /// The offsets needed to touch loaded files were found, but these symbols are missing or
+    /// couldn't be checked against the version's offset table
+    Partial(Vec<String>),
+    /// The game version has no offset table, only the signature scan was used
+    UnknownVersion,
+    /// The signature scan disagrees with the version's offset table for these symbols
+    Mismatch(Vec<String>),
+    /// These symbols, needed to touch loaded files, were not found at all
+    Unsupported(Vec<String>),
+}
+
+impl GameSupport {
+    pub fn to_token(&self) -> String {
+        match self {
+            GameSupport::Verified => "verified".to_string(),
+            GameSupport::Partial(symbols) => format!("{}={}", PARTIAL, symbols.join(",")),
+            GameSupport::UnknownVersion => "unknown-version".to_string(),
+            GameSupport::Mismatch(symbols) => format!("{}={}", MISMATCH, symbols.join(",")),
+            GameSupport::Unsupported(symbols) => {
+                format!("{}={}", UNSUPPORTED, symbols.join(","))
+            }
+        }
+    }
+
+    pub fn parse_token(token: &str) -> Option<Self> {
+        match token {
+            "verified" => return Some(GameSupport::Verified),
+            "unknown-version" => return Some(GameSupport::UnknownVersion),
+            _ => {}
+        }
+        let mut parts = token.splitn(2, '=');
+        let kind = parts.next()?;
+        let symbols = parts.next()?.split(',').map(String::from).collect();
+        match kind {
+            PARTIAL => Some(GameSupport::Partial(symbols)),
+            MISMATCH => Some(GameSupport::Mismatch(symbols)),
+            UNSUPPORTED => Some(GameSupport::Unsupported(symbols)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GameSupport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameSupport::Verified => write!(f, "offsets verified"),
+            GameSupport::Partial(symbols) => write!(
+                f,
+                "offsets not fully verified for this game version ({})",
+                symbols.join(", ")
+            ),
+            GameSupport::UnknownVersion => {
+                write!(f, "unknown game version, the plugin may need updating")
+            }
+            GameSupport::Mismatch(symbols) => write!(
+                f,
+                "offsets do not match this game version ({}), the plugin needs updating",
+                symbols.join(", ")
+            ),
+            GameSupport::Unsupported(symbols) => write!(
+                f,
+                "offsets not found ({}), the plugin needs updating",
+                symbols.join(", ")
+            ),
+        }
+    }
+}
+
+/// The server's answer to a `HELLO`:
+/// `HELLO <protocol version> <plugin version> <game version> <game support>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerHello {
+    pub version: u32,
+    pub plugin_version: String,
+    pub game_version: String,
+    /// `None` when talking to a server that predates version detection
+    pub game_support: Option<GameSupport>,
+}
+
+impl ServerHello {
+    pub fn new(plugin_version: &str, game_version: &str, game_support: GameSupport) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            plugin_version: plugin_version.to_string(),
+            game_version: game_version.to_string(),
+            game_support: Some(game_support),
+        }
+    }
+
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+        if parts.next()? != HELLO {
+            return None;
+        }
+        Some(Self {
+            version: parts.next()?.parse().ok()?,
+            plugin_version: parts.next()?.to_string(),
+            game_version: parts.next().unwrap_or("unknown").to_string(),
+            game_support: parts.next().and_then(GameSupport::parse_token),
+        })
+    }
+
+    /// Finds the server's `HELLO` at the start of a response.
+    pub fn from_response(response: &str) -> Option<Self> {
+        Self::parse_line(response.lines().next()?)
+    }
+
+    pub fn to_line(&self) -> String {
+        let mut line = format!(
+            "{} {} {} {}",
+            HELLO, self.version, self.plugin_version, self.game_version
+        );
+        if let Some(game_support) = &self.game_support {
+            line.push(' ');
+            line.push_str(&game_support.to_token());
+        }
+        line
+    }
+}
+
+impl fmt::Display for ServerHello {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "auto-refresh {} on Smash {}",
+            self.plugin_version, self.game_version
+        )?;
+        match &self.game_support {
+            Some(game_support) => write!(f, ", {}", game_support),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub hello: Option<Hello>,
+    pub command: Command,
+}
+
+impl Request {
+    pub fn new(command: Command, token: Option<String>) -> Self {
+        Self {
+            hello: Some(Hello::new(token)),
+            command,
+        }
+    }
+
+    pub fn parse(data: &str) -> Self {
+        let data = data.trim_start();
+        let first_line = data.lines().next().unwrap_or("");
+        match Hello::parse_line(first_line) {
+            Some(hello) => Self {
+                hello: Some(hello),
+                command: Command::parse(&data[first_line.len()..]),
+            },
+            None => Self {
+                hello: None,
+                command: Command::parse(data),
+            },
+        }
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.hello.as_ref().and_then(|hello| hello.token.as_deref())
+    }
+
+    /// The request, ended by an empty line. `overlay_request` builds `OVERLAY` requests, which
+    /// carry bytes after the text.
+    pub fn to_request(&self) -> String {
+        match &self.hello {
+            Some(hello) => format!("{}\n{}\n", hello.to_line(), self.command.to_request()),
+            None => format!("{}\n", self.command.to_request()),
+        }
+    }
+}
+
+pub fn denied_line(reason: &str) -> String {
+    format!("{} {}", DENIED, reason)
+}
+
+/// Returns the reason if the server refused the request.
+pub fn parse_denied(response: &str) -> Option<&str> {
+    let mut parts = response.lines().next()?.trim().splitn(2, ' ');
+    if parts.next()? != DENIED {
+        return None;
+    }
+    Some(parts.next().unwrap_or("").trim())
+}
+
+pub fn cursor_line(cursor: u64) -> String {
+    format!("cursor\t{}", cursor)
+}
+
+/// The cursor a `PENDING` response ends with.
+pub fn parse_cursor(response: &str) -> Option<u64> {
+    response.lines().find_map(|line| {
+        let mut parts = line.trim().splitn(2, '\t');
+        if parts.next()? != "cursor" {
+            return None;
+        }
+        parts.next()?.parse().ok()
+    })
+}
+
+pub fn dump_line(path: &str, size: usize) -> String {
+    format!("dump\t{}\t{}", path, size)
+}
+
+/// Bytes of a loaded file, split out of a `DUMP` response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dump<'a> {
+    pub path: String,
+    pub data: &'a [u8],
+}
+
+/// Finds the dumped file in a `DUMP` response, or the reason the server gave for not sending it.
+pub fn parse_dump(response: &[u8]) -> Result<Dump<'_>, String> {
+    let mut rest = response;
+    while !rest.is_empty() {
+        let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+        let line = String::from_utf8_lossy(&rest[..end]).into_owned();
+        rest = rest.get(end + 1..).unwrap_or(&[]);
+
+        let mut parts = line.trim_end().splitn(3, '\t');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("dump"), Some(path), Some(size)) => {
+                let size: usize = size
+                    .parse()
+                    .map_err(|_| format!("invalid dump size {:?}", size))?;
+                if rest.len() != size {
+                    return Err(format!(
+                        "expected {} bytes for {}, received {}",
+                        size,
+                        path,
+                        rest.len()
+                    ));
+                }
+                return Ok(Dump {
+                    path: path.to_string(),
+                    data: rest,
+                });
+            }
+            _ => {
+                if let Some(result) = FileResult::parse_line(&line) {
+                    return Err(format!("{}", result));
+                }
+            }
+        }
+    }
+    Err("the server did not send a dump".to_string())
+}
+
+/// A file provided by more than one mod folder. The first folder by name is used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootConflict {
+    pub path: String,
+    pub used_root: String,
+    pub ignored_root: String,
+}
+
+impl RootConflict {
+    pub fn to_line(&self) -> String {
+        format!(
+            "conflict\t{}\t{}\t{}",
+            self.path, self.used_root, self.ignored_root
+        )
+    }
+
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let mut parts = line.trim_end().splitn(4, '\t');
+        if parts.next()? != "conflict" {
+            return None;
+        }
+        Some(Self {
+            path: parts.next()?.to_string(),
+            used_root: parts.next()?.to_string(),
+            ignored_root: parts.next()?.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for RootConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is in both {} and {}, using {}",
+            self.path, self.used_root, self.ignored_root, self.used_root
+        )
+    }
+}
+
+/// Every conflict listed in a `RESCAN` response.
+pub fn parse_conflicts(response: &str) -> Vec<RootConflict> {
+    response
+        .lines()
+        .filter_map(RootConflict::parse_line)
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileStatus {
+    Refreshed,
+    /// Only part of the file could be refreshed, e.g. some textures of a BNTX
+    Partial(String),
+    /// The file isn't loaded, the refresh will be resolved when the game loads it
+    Pending(String),
+    /// The file isn't kept in memory, e.g. a stream file, the game uses the new one the next
+    /// time it opens it
+    Reopen(String),
+    Skipped(String),
+}
+
+/// Outcome of refreshing a single file, sent back as one line of the response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileResult {
+    pub path: String,
+    pub status: FileStatus,
+}
+
+impl FileResult {
+    pub fn refreshed<S: Into<String>>(path: S) -> Self {
+        Self {
+            path: path.into(),
+            status: FileStatus::Refreshed,
+        }
+    }
+
+    pub fn partial<S: Into<String>, R: Into<String>>(path: S, reason: R) -> Self {
+        Self {
+            path: path.into(),
+            status: FileStatus::Partial(reason.into()),
+        }
+    }
+
+    pub fn pending<S: Into<String>, R: Into<String>>(path: S, reason: R) -> Self {
+        Self {
+            path: path.into(),
+            status: FileStatus::Pending(reason.into()),
+        }
+    }
+
+    pub fn reopen<S: Into<String>, R: Into<String>>(path: S, reason: R) -> Self {
+        Self {
+            path: path.into(),
+            status: FileStatus::Reopen(reason.into()),
+        }
+    }
+
+    pub fn skipped<S: Into<String>, R: Into<String>>(path: S, reason: R) -> Self {
+        Self {
+            path: path.into(),
+            status: FileStatus::Skipped(reason.into()),
+        }
+    }
+
+    pub fn is_refreshed(&self) -> bool {
+        self.status == FileStatus::Refreshed
+    }
+
+    pub fn to_line(&self) -> String {
+        match &self.status {
+            FileStatus::Refreshed => format!("refreshed\t{}", self.path),
+            FileStatus::Partial(reason) => format!("partial\t{}\t{}", self.path, reason),
+            FileStatus::Pending(reason) => format!("pending\t{}\t{}", self.path, reason),
+            FileStatus::Reopen(reason) => format!("reopen\t{}\t{}", self.path, reason),
+            FileStatus::Skipped(reason) => format!("skipped\t{}\t{}", self.path, reason),
+        }
+    }
+
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let mut parts = line.trim_end().splitn(3, '\t');
+        match (parts.next()?, parts.next()) {
+            ("refreshed", Some(path)) => Some(Self::refreshed(path)),
+            ("partial", Some(path)) => Some(Self::partial(path, parts.next().unwrap_or(""))),
+            ("pending", Some(path)) => Some(Self::pending(path, parts.next().unwrap_or(""))),
+            ("reopen", Some(path)) => Some(Self::reopen(path, parts.next().unwrap_or(""))),
+            ("skipped", Some(path)) => Some(Self::skipped(path, parts.next().unwrap_or(""))),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FileResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.status {
+            FileStatus::Refreshed => write!(f, "Refreshed {}", self.path),
+            FileStatus::Partial(reason) => {
+                write!(f, "Partially refreshed {} ({})", self.path, reason)
+            }
+            FileStatus::Pending(reason) => write!(f, "Pending {} ({})", self.path, reason),
+            FileStatus::Reopen(reason) => write!(f, "Re-open {} ({})", self.path, reason),
+            FileStatus::Skipped(reason) => write!(f, "Skipped {} ({})", self.path, reason),
+        }
+    }
+}
+
+/// Per-file results of a refresh request.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    pub results: Vec<FileResult>,
+}
+
+impl Summary {
+    pub fn parse(response: &str) -> Self {
+        Self {
+            results: response.lines().filter_map(FileResult::parse_line).collect(),
+        }
+    }
+
+    pub fn refreshed_count(&self) -> usize {
+        self.results.iter().filter(|result| result.is_refreshed()).count()
+    }
+
+    pub fn partial_count(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| matches!(result.status, FileStatus::Partial(_)))
+            .count()
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| matches!(result.status, FileStatus::Pending(_)))
+            .count()
+    }
+
+    pub fn reopen_count(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| matches!(result.status, FileStatus::Reopen(_)))
+            .count()
+    }
+
+    pub fn skipped_count(&self) -> usize {
+        self.results.len()
+            - self.refreshed_count()
+            - self.partial_count()
+            - self.pending_count()
+            - self.reopen_count()
+    }
+
+    pub fn to_response(&self) -> String {
+        let mut response = String::new();
+        for result in &self.results {
+            response.push_str(&result.to_line());
+            response.push('\n');
+        }
+        response
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} refreshed, {} partial, {} pending, {} to re-open, {} skipped",
+            self.refreshed_count(),
+            self.partial_count(),
+            self.pending_count(),
+            self.reopen_count(),
+            self.skipped_count()
+        )?;
+        for result in &self.results {
+            writeln!(f, "{}", result)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bytes of a file pushed with `OVERLAY`, split out of the request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overlay<'a> {
+    pub path: String,
+    pub data: &'a [u8],
+}
+
+/// An `OVERLAY` request carrying `data`, ready to send.
+pub fn overlay_request(hello: Option<&Hello>, path: &str, data: &[u8]) -> Vec<u8> {
+    let command = Command::Overlay {
+        path: path.to_string(),
+        size: data.len(),
+    };
+    let mut request = match hello {
+        Some(hello) => format!("{}\n{}", hello.to_line(), command.to_request()),
+        None => command.to_request(),
+    }
+    .into_bytes();
+    request.extend_from_slice(data);
+    request
+}
+
+/// End of the line starting at `start`, past its `\n`.
+fn line_end(data: &[u8], start: usize) -> usize {
+    data[start..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(data.len(), |position| start + position + 1)
+}
+
+/// The next complete line starting at `start`, if all of it arrived.
+fn complete_line(data: &[u8], start: usize) -> Option<(Cow<'_, str>, usize)> {
+    if start >= data.len() {
+        return None;
+    }
+    let end = line_end(data, start);
+    if data[end - 1] != b'\n' {
+        return None;
+    }
+    Some((String::from_utf8_lossy(&data[start..end]), end))
+}
+
+/// Length of the request at the start of `data`, or `None` while the rest of it hasn't arrived.
+/// Lets the server answer without waiting for the client to close its write half.
+pub fn request_len(data: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    let mut seen_hello = false;
+    loop {
+        let (line, end) = complete_line(data, start)?;
+        let line = line.trim();
+        start = end;
+        if line.is_empty() {
+            continue;
+        }
+        if !seen_hello && Hello::parse_line(line).is_some() {
+            seen_hello = true;
+            continue;
+        }
+
+        if line == OVERLAY {
+            let (size_line, header_end) = complete_line(data, start)?;
+            let size = match size_line.trim().rsplit('\t').next().map(str::parse::<usize>) {
+                Some(Ok(size)) => size,
+                // Malformed, the command reports it
+                _ => return Some(header_end),
+            };
+            let total = header_end.saturating_add(size);
+            return if data.len() >= total { Some(total) } else { None };
+        }
+        if TEXT_COMMANDS.contains(&line) {
+            loop {
+                let (line, end) = complete_line(data, start)?;
+                if line.trim().is_empty() {
+                    return Some(end);
+                }
+                start = end;
+            }
+        }
+        // A bare ARC path
+        return Some(end);
+    }
+}
+
+/// The text lines of a raw request: all of it, except for an `OVERLAY` where the payload
+/// following its `<path>\t<size>` line is left out.
+pub fn request_header(request: &[u8]) -> &[u8] {
+    let mut start = 0;
+    while start < request.len() {
+        let end = line_end(request, start);
+        let is_overlay = String::from_utf8_lossy(&request[start..end]).trim() == OVERLAY;
+        start = end;
+        if is_overlay {
+            return &request[..line_end(request, start.min(request.len()))];
+        }
+    }
+    request
+}
+
+/// Finds the pushed file in an `OVERLAY` request, or why it can't be used.
+pub fn parse_overlay(request: &[u8]) -> Result<Overlay<'_>, String> {
+    let mut rest = request;
+    while !rest.is_empty() {
+        let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+        let is_overlay = String::from_utf8_lossy(&rest[..end]).trim() == OVERLAY;
+        rest = rest.get(end + 1..).unwrap_or(&[]);
+        if !is_overlay {
+            continue;
+        }
+
+        let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+        let line = String::from_utf8_lossy(&rest[..end]).into_owned();
+        rest = rest.get(end + 1..).unwrap_or(&[]);
+        let mut parts = line.trim_end().splitn(2, '\t');
+        let (path, size) = match (parts.next(), parts.next()) {
+            (Some(path), Some(size)) => (path, size),
+            _ => return Err(format!("invalid overlay header {:?}", line)),
+        };
+        let size: usize = size
+            .parse()
+            .map_err(|_| format!("invalid overlay size {:?}", size))?;
+        if rest.len() != size {
+            return Err(format!(
+                "expected {} bytes for {}, received {}",
+                size,
+                path,
+                rest.len()
+            ));
+        }
+        return Ok(Overlay {
+            path: path.to_string(),
+            data: rest,
+        });
+    }
+    Err("the request has no overlay".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay_header_leaves_out_the_payload() {
+        let hello = Hello::new(Some("hunter2".to_string()));
+        let payload = [0xFF, b'\n', 0x00, b'R', b'E'];
+        let request = overlay_request(Some(&hello), "ui/a.bntx", &payload);
+
+        let header = request_header(&request);
+        assert_eq!(header.len(), request.len() - payload.len());
+        let parsed = Request::parse(&String::from_utf8_lossy(header));
+        assert_eq!(parsed.token(), Some("hunter2"));
+        assert_eq!(
+            parsed.command,
+            Command::Overlay {
+                path: "ui/a.bntx".to_string(),
+                size: payload.len(),
+            }
+        );
+        assert_eq!(parse_overlay(&request).unwrap().data, &payload[..]);
+    }
+
+    #[test]
+    fn other_headers_are_the_whole_request() {
+        let request = Request::new(Command::Refresh(vec!["ui/a.bntx".to_string()]), None);
+        let bytes = request.to_request().into_bytes();
+        assert_eq!(request_header(&bytes), &bytes[..]);
+        assert_eq!(request_header(b""), b"");
+    }
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn every_command_round_trips() {
+        let commands = vec![
+            Command::Refresh(paths(&["ui/a.bntx", "fighter/mario/b.nutexb"])),
+            Command::RefreshAll,
+            Command::Rescan,
+            Command::Status,
+            Command::Pending(None),
+            Command::Restore(paths(&["ui/a.bntx"])),
+            Command::RestoreAll,
+            Command::Dump("ui/a.bntx".to_string()),
+            Command::TraceStart,
+            Command::TraceStop,
+            Command::LogSubscribe(Level::Warn),
+            Command::Overlay {
+                path: "ui/a.bntx".to_string(),
+                size: 0x1234,
+            },
+            Command::Persist(vec![]),
+            Command::Persist(paths(&["ui/a.bntx"])),
+            Command::Ping,
+        ];
+        for command in commands {
+            assert_eq!(Command::parse(&command.to_request()), command);
+
+            let request = Request::new(command.clone(), Some("hunter2".to_string()));
+            let parsed = Request::parse(&request.to_request());
+            assert_eq!(parsed.token(), Some("hunter2"));
+            assert_eq!(parsed.command, command);
+        }
+    }
+
+    #[test]
+    fn requests_send_forward_slashes() {
+        let command = Command::Refresh(paths(&["ui\\replace\\a.bntx"]));
+        assert_eq!(command.to_request(), "REFRESH\nui/replace/a.bntx\n");
+    }
+
+    #[test]
+    fn bare_paths_are_a_legacy_refresh() {
+        let request = Request::parse("ui/a.bntx\r\n\n  ui/b.bntx  \n");
+        assert_eq!(request.hello, None);
+        assert_eq!(request.token(), None);
+        assert_eq!(
+            request.command,
+            Command::Refresh(paths(&["ui/a.bntx", "ui/b.bntx"]))
+        );
+        assert_eq!(Command::parse(""), Command::Refresh(vec![]));
+    }
+
+    #[test]
+    fn file_results_round_trip() {
+        let results = vec![
+            FileResult::refreshed("ui/a.bntx"),
+            FileResult::partial("ui/b.bntx", "tex_0: width 64 (loaded 32)"),
+            FileResult::pending("ui/c.bntx", "waiting for the game to load it"),
+            FileResult::reopen("stream:/sound/bgm/a.nus3audio", "re-open it in game"),
+            FileResult::skipped("ui/d.bntx", "reason\twith a tab"),
+        ];
+        let summary = Summary { results };
+        let parsed = Summary::parse(&format!(
+            "HELLO 1 0.1.0 13.0.1 verified\n{}",
+            summary.to_response()
+        ));
+        assert_eq!(parsed, summary);
+        assert_eq!(
+            (
+                parsed.refreshed_count(),
+                parsed.partial_count(),
+                parsed.pending_count(),
+                parsed.reopen_count(),
+                parsed.skipped_count()
+            ),
+            (1, 1, 1, 1, 1)
+        );
+        assert_eq!(FileResult::parse_line("refreshed"), None);
+        assert_eq!(FileResult::parse_line("unknown\tui/a.bntx"), None);
+        assert_eq!(
+            FileResult::parse_line("skipped\tui/a.bntx"),
+            Some(FileResult::skipped("ui/a.bntx", ""))
+        );
+    }
+
+    #[test]
+    fn dumps_split_out_of_the_response() {
+        let mut response = format!(
+            "HELLO 1 0.1.0 13.0.1 verified\n{}\n",
+            dump_line("ui/a.bntx", 3)
+        )
+        .into_bytes();
+        response.extend_from_slice(&[0, b'\n', 0xFF]);
+        let dump = parse_dump(&response).unwrap();
+        assert_eq!(dump.path, "ui/a.bntx");
+        assert_eq!(dump.data, &[0, b'\n', 0xFF][..]);
+
+        response.pop();
+        assert_eq!(
+            parse_dump(&response),
+            Err("expected 3 bytes for ui/a.bntx, received 2".to_string())
+        );
+        let skipped = format!(
+            "{}\n",
+            FileResult::skipped("ui/a.bntx", "not loaded").to_line()
+        );
+        assert_eq!(
+            parse_dump(skipped.as_bytes()),
+            Err("Skipped ui/a.bntx (not loaded)".to_string())
+        );
+        assert!(parse_dump(b"").is_err());
+    }
+
+    #[test]
+    fn pending_cursors_round_trip() {
+        let command = Command::Pending(Some(42));
+        assert_eq!(Command::parse(&command.to_request()), command);
+        let response = format!(
+            "{}\n{}\n",
+            FileResult::refreshed("ui/a").to_line(),
+            cursor_line(7)
+        );
+        assert_eq!(parse_cursor(&response), Some(7));
+        assert_eq!(Summary::parse(&response).results.len(), 1);
+        assert_eq!(parse_cursor("refreshed\tui/a\n"), None);
+    }
+
+    #[test]
+    fn game_support_round_trips() {
+        let symbols = vec!["filesystem_info".to_string(), "res_service".to_string()];
+        for support in &[
+            GameSupport::Verified,
+            GameSupport::Partial(symbols.clone()),
+            GameSupport::UnknownVersion,
+            GameSupport::Mismatch(symbols.clone()),
+            GameSupport::Unsupported(symbols),
+        ] {
+            assert_eq!(
+                GameSupport::parse_token(&support.to_token()).as_ref(),
+                Some(support)
+            );
+        }
+        assert_eq!(GameSupport::parse_token("mismatch"), None);
+        assert_eq!(GameSupport::parse_token("other=a"), None);
+    }
+
+    #[test]
+    fn rescan_conflicts_round_trip() {
+        let conflict = RootConflict {
+            path: "fighter/mario/model/body/c00/model.numdlb".to_string(),
+            used_root: "sd:/ultimate/mods/A mod".to_string(),
+            ignored_root: "sd:/ultimate/mods/B mod".to_string(),
+        };
+        let response = format!("Rescanned! 12 files, 1 conflicts\n{}\n", conflict.to_line());
+        assert_eq!(parse_conflicts(&response), vec![conflict]);
+        assert_eq!(RootConflict::parse_line("conflict\tui/a\tsd:/x"), None);
+    }
+
+    #[test]
+    fn text_requests_end_at_an_empty_line() {
+        let request = Request::new(Command::Refresh(paths(&["ui/a.bntx"])), None).to_request();
+        let bytes = request.as_bytes();
+        assert_eq!(request_len(bytes), Some(bytes.len()));
+        for partial in 0..bytes.len() {
+            assert_eq!(request_len(&bytes[..partial]), None);
+        }
+
+        let mut pipelined = bytes.to_vec();
+        pipelined.extend_from_slice(b"STATUS\n\n");
+        assert_eq!(request_len(&pipelined), Some(bytes.len()));
+        assert_eq!(request_len(b"\r\nSTATUS\r\n\r\n"), Some(12));
+    }
+
+    #[test]
+    fn overlay_requests_end_after_their_bytes() {
+        let request = overlay_request(None, "ui/a.bntx", b"\n\nabc");
+        assert_eq!(request_len(&request), Some(request.len()));
+        assert_eq!(request_len(&request[..request.len() - 1]), None);
+        assert_eq!(request_len(b"OVERLAY\nui/a.bntx\t3"), None);
+        // Without a size nothing more is read, parsing the request reports it
+        assert_eq!(request_len(b"OVERLAY\nui/a.bntx\tx\nabc"), Some(20));
+    }
+
+    #[test]
+    fn bare_paths_end_with_their_line() {
+        assert_eq!(request_len(b"ui/a.bntx"), None);
+        assert_eq!(request_len(b"ui/a.bntx\n"), Some(10));
+        assert_eq!(request_len(b"HELLO 1\nui/a.bntx\nui/b.bntx\n"), Some(18));
+    }
+
+    #[test]
+    fn tokens_may_contain_spaces() {
+        let hello = Hello::new(Some("correct horse battery".to_string()));
+        assert_eq!(Hello::parse_line(&hello.to_line()), Some(hello));
+        assert_eq!(Hello::parse_line("HELLO 1  "), Some(Hello::new(None)));
+        assert_eq!(Hello::parse_line("HELLO x token"), None);
+    }
+
+    #[test]
+    fn tokens_match_only_exactly() {
+        assert!(token_matches("hunter2", "hunter2"));
+        assert!(token_matches("", ""));
+        assert!(!token_matches("hunter2", "hunter"));
+        assert!(!token_matches("hunter2", "hunter22"));
+        assert!(!token_matches("hunter2", "Hunter2"));
+        assert!(!token_matches("hunter2", ""));
+    }
+
+    #[test]
+    fn truncated_overlay_headers_stop_at_the_end() {
+        assert_eq!(request_header(b"OVERLAY\n"), b"OVERLAY\n");
+        assert_eq!(request_header(b"OVERLAY"), b"OVERLAY");
+        assert_eq!(request_header(b"OVERLAY\nui/a\t3"), b"OVERLAY\nui/a\t3");
+    }
+}
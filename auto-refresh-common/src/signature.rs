@@ -0,0 +1,316 @@
+//! Byte-signature scanning over a dump of the game's `.text`.
+//!
+//! Patterns are written as hex bytes separated by spaces, with `??` matching any byte:
+//! `"f3 03 00 aa ?? ?? 09 6b"`. Each symbol can list several candidate patterns so that a
+//! single plugin build keeps working across game updates.
+
+use std::convert::TryInto;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternError {
+    pub pattern: String,
+    pub token: String,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid byte {:?} in pattern {:?}", self.token, self.pattern)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+/// A byte sequence where `None` matches anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    bytes: Vec<Option<u8>>,
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Self, PatternError> {
+        let bytes = pattern
+            .split_whitespace()
+            .map(|token| match token {
+                "?" | "??" => Ok(None),
+                _ => u8::from_str_radix(token, 16)
+                    .map(Some)
+                    .map_err(|_| PatternError {
+                        pattern: pattern.to_string(),
+                        token: token.to_string(),
+                    }),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { bytes })
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            bytes: bytes.iter().copied().map(Some).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn matches(&self, window: &[u8]) -> bool {
+        self.bytes
+            .iter()
+            .zip(window)
+            .all(|(expected, byte)| expected.is_none() || *expected == Some(*byte))
+    }
+
+    /// Offset of the first match in `haystack`.
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+        haystack
+            .windows(self.len())
+            .position(|window| self.matches(window))
+    }
+}
+
+/// One way of finding a symbol: where `pattern` matches, the symbol is `delta` bytes further.
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate {
+    pub pattern: &'static str,
+    pub delta: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Signature {
+    pub name: &'static str,
+    /// Tried in order, the first match wins
+    pub candidates: &'static [Candidate],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    /// Index into `Signature::candidates`
+    pub candidate: usize,
+    /// Where the pattern itself matched
+    pub pattern_offset: usize,
+    /// `pattern_offset` plus the candidate's delta
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanResult {
+    pub name: &'static str,
+    pub found: Result<Match, String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanReport {
+    pub results: Vec<ScanResult>,
+}
+
+impl ScanReport {
+    pub fn get(&self, name: &str) -> Option<&Match> {
+        self.results
+            .iter()
+            .find(|result| result.name == name)
+            .and_then(|result| result.found.as_ref().ok())
+    }
+
+    pub fn offset(&self, name: &str) -> Option<usize> {
+        self.get(name).map(|found| found.offset)
+    }
+
+    pub fn missing(&self) -> Vec<&'static str> {
+        self.results
+            .iter()
+            .filter(|result| result.found.is_err())
+            .map(|result| result.name)
+            .collect()
+    }
+}
+
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for result in &self.results {
+            match &result.found {
+                Ok(found) => writeln!(
+                    f,
+                    "{}: {:#x} (candidate {} matched at {:#x})",
+                    result.name, found.offset, found.candidate, found.pattern_offset
+                )?,
+                Err(reason) => writeln!(f, "{}: not found ({})", result.name, reason)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn find_signature(text: &[u8], signature: &Signature) -> Result<Match, String> {
+    for (i, candidate) in signature.candidates.iter().enumerate() {
+        let pattern = Pattern::parse(candidate.pattern).map_err(|err| err.to_string())?;
+        if let Some(pattern_offset) = pattern.find(text) {
+            return Ok(Match {
+                candidate: i,
+                pattern_offset,
+                offset: pattern_offset + candidate.delta,
+            });
+        }
+    }
+    Err(format!(
+        "none of {} candidate patterns matched",
+        signature.candidates.len()
+    ))
+}
+
+pub fn scan(text: &[u8], signatures: &[Signature]) -> ScanReport {
+    ScanReport {
+        results: signatures
+            .iter()
+            .map(|signature| ScanResult {
+                name: signature.name,
+                found: find_signature(text, signature),
+            })
+            .collect(),
+    }
+}
+
+pub fn read_instruction(text: &[u8], offset: usize) -> Option<u32> {
+    let bytes = text.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+const ADRP_MASK: u32 = 0x9F00_0000;
+const ADRP_OPCODE: u32 = 0x9000_0000;
+/// `ldr` (immediate, unsigned offset) into a W or X register
+const LDR_MASK: u32 = 0xBFC0_0000;
+const LDR_OPCODE: u32 = 0xB940_0000;
+
+/// Page targeted by an `adrp` located at `offset`, `None` if `adrp` is another instruction.
+#[allow(clippy::inconsistent_digit_grouping)]
+pub fn decode_adrp(adrp: u32, offset: usize) -> Option<usize> {
+    if adrp & ADRP_MASK != ADRP_OPCODE {
+        return None;
+    }
+    let immhi = (adrp & 0b0000_0000_1111_1111_1111_1111_1110_0000) >> 3;
+    let immlo = (adrp & 0b0110_0000_0000_0000_0000_0000_0000_0000) >> 29;
+    // 21 bit signed page count
+    let pages = (((immhi | immlo) << 11) as i32 >> 11) as i64;
+    let base = (offset & !0xFFF) as i64;
+    Some((base + (pages << 12)) as usize)
+}
+
+/// Immediate offset of an unsigned-offset `ldr`, scaled by its access size. `None` for any
+/// other instruction.
+#[allow(clippy::inconsistent_digit_grouping)]
+pub fn decode_ldr(ldr: u32) -> Option<usize> {
+    if ldr & LDR_MASK != LDR_OPCODE {
+        return None;
+    }
+    let size = (ldr & 0b1100_0000_0000_0000_0000_0000_0000_0000) >> 30;
+    let imm = (ldr & 0b0000_0000_0011_1111_1111_1100_0000_0000) >> 10;
+    Some((imm as usize) << size)
+}
+
+/// Resolves an `adrp` at `offset` followed by an `ldr` from the same register.
+pub fn resolve_adrp_ldr(text: &[u8], offset: usize) -> Option<usize> {
+    let adrp = read_instruction(text, offset)?;
+    let ldr = read_instruction(text, offset.checked_add(4)?)?;
+    // The ldr's base register has to be the one the adrp wrote
+    if (ldr >> 5) & 0x1F != adrp & 0x1F {
+        return None;
+    }
+    Some(decode_adrp(adrp, offset)? + decode_ldr(ldr)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Captured from `llvm-mc -triple=aarch64 -show-encoding`
+    /// adrp x8, #-0x3000
+    const ADRP_X8_BACK_3_PAGES: u32 = 0xB0FF_FFE8;
+    /// adrp x8, #0x2a5c000
+    const ADRP_X8_FORWARD: u32 = 0x9001_52E8;
+    /// ldr x8, [x8, #0x6f0]
+    const LDR_X8_X8: u32 = 0xF943_7908;
+    /// ldr w9, [x9, #0x1c]
+    const LDR_W9_X9: u32 = 0xB940_1D29;
+    /// ldr x0, [x8, #0x6f0]
+    const LDR_X0_X8: u32 = 0xF943_7900;
+    /// add x8, x8, #0x6f0
+    const ADD_X8: u32 = 0x911B_C108;
+    /// adr x8, #0x10
+    const ADR_X8: u32 = 0x1000_0088;
+    /// ldur x8, [x8, #-8]
+    const LDUR_X8: u32 = 0xF85F_8108;
+    /// mov x0, x1
+    const MOV_X0_X1: u32 = 0xAA01_03E0;
+
+    fn text_at(offset: usize, instructions: &[u32]) -> Vec<u8> {
+        let mut text = vec![0; offset];
+        for instruction in instructions {
+            text.extend_from_slice(&instruction.to_le_bytes());
+        }
+        text
+    }
+
+    #[test]
+    fn decodes_forward_adrp() {
+        assert_eq!(decode_adrp(ADRP_X8_FORWARD, 0), Some(0x2a5_c000));
+        assert_eq!(decode_adrp(ADRP_X8_FORWARD, 0x1000), Some(0x2a5_d000));
+    }
+
+    #[test]
+    fn decodes_negative_adrp() {
+        assert_eq!(decode_adrp(ADRP_X8_BACK_3_PAGES, 0x10_0000), Some(0xf_d000));
+        assert_eq!(decode_adrp(ADRP_X8_BACK_3_PAGES, 0x3000), Some(0));
+    }
+
+    #[test]
+    fn adrp_works_on_the_page_of_its_own_address() {
+        assert_eq!(decode_adrp(ADRP_X8_FORWARD, 0x1ffc), Some(0x2a5_d000));
+        assert_eq!(decode_adrp(ADRP_X8_BACK_3_PAGES, 0x10_0ff8), Some(0xf_d000));
+    }
+
+    #[test]
+    fn scales_ldr_offsets_by_access_size() {
+        assert_eq!(decode_ldr(LDR_X8_X8), Some(0x6f0));
+        assert_eq!(decode_ldr(LDR_W9_X9), Some(0x1c));
+    }
+
+    #[test]
+    fn rejects_other_instructions() {
+        for instruction in &[ADR_X8, ADD_X8, MOV_X0_X1, LDR_X8_X8] {
+            assert_eq!(decode_adrp(*instruction, 0), None);
+        }
+        for instruction in &[LDUR_X8, ADD_X8, MOV_X0_X1, ADRP_X8_FORWARD] {
+            assert_eq!(decode_ldr(*instruction), None);
+        }
+    }
+
+    #[test]
+    fn resolves_adrp_ldr_pairs() {
+        let text = text_at(0x1_2344, &[ADRP_X8_FORWARD, LDR_X8_X8]);
+        assert_eq!(resolve_adrp_ldr(&text, 0x1_2344), Some(0x2a6_e6f0));
+        let text = text_at(0x10_0ff8, &[ADRP_X8_BACK_3_PAGES, LDR_X0_X8]);
+        assert_eq!(resolve_adrp_ldr(&text, 0x10_0ff8), Some(0xf_d6f0));
+    }
+
+    #[test]
+    fn rejects_mismatched_pairs() {
+        // Loads from another register than the adrp wrote
+        let text = text_at(0, &[ADRP_X8_FORWARD, LDR_W9_X9]);
+        assert_eq!(resolve_adrp_ldr(&text, 0), None);
+        let text = text_at(0, &[ADD_X8, LDR_X8_X8]);
+        assert_eq!(resolve_adrp_ldr(&text, 0), None);
+        let text = text_at(0, &[ADRP_X8_FORWARD, LDUR_X8]);
+        assert_eq!(resolve_adrp_ldr(&text, 0), None);
+        // Cut off after the adrp
+        let text = text_at(0, &[ADRP_X8_FORWARD]);
+        assert_eq!(resolve_adrp_ldr(&text, 0), None);
+        assert_eq!(resolve_adrp_ldr(&text, usize::MAX - 2), None);
+    }
+}
@@ -0,0 +1,146 @@
+//! Copies of loaded buffers taken before the server first overwrites them, so a refresh can be
+//! undone without restarting the game.
+//!
+//! Only the first overwrite of a file is captured: later refreshes must not replace the
+//! original with an edit. Snapshots are never evicted to make room for new ones, since that
+//! would silently lose an original. Once the budget is used up, new files go unsnapshotted.
+
+use std::collections::HashMap;
+
+pub const DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub path: String,
+    /// Address of the buffer the bytes were copied from. BNTX buffers hold pointers relocated
+    /// against it.
+    pub data_ptr: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Recorded {
+    /// The bytes were copied into a new snapshot
+    New,
+    /// The file already had a snapshot, which was kept
+    Existing,
+    /// Snapshotting the file would go over the budget
+    OverBudget,
+}
+
+#[derive(Debug)]
+pub struct SnapshotStore {
+    entries: HashMap<u64, Snapshot>,
+    budget: usize,
+    used: usize,
+}
+
+impl SnapshotStore {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            budget,
+            used: 0,
+        }
+    }
+
+    /// Snapshots `original` unless `hash` already has a snapshot or it doesn't fit the budget.
+    pub fn record(&mut self, hash: u64, path: &str, data_ptr: u64, original: &[u8]) -> Recorded {
+        if self.entries.contains_key(&hash) {
+            return Recorded::Existing;
+        }
+        if self.used + original.len() > self.budget {
+            return Recorded::OverBudget;
+        }
+        self.used += original.len();
+        self.entries.insert(
+            hash,
+            Snapshot {
+                path: path.to_string(),
+                data_ptr,
+                data: original.to_vec(),
+            },
+        );
+        Recorded::New
+    }
+
+    pub fn get(&self, hash: u64) -> Option<&Snapshot> {
+        self.entries.get(&hash)
+    }
+
+    /// Drops the snapshot of `hash`, once it has been written back.
+    pub fn remove(&mut self, hash: u64) -> Option<Snapshot> {
+        let snapshot = self.entries.remove(&hash)?;
+        self.used -= snapshot.data.len();
+        Some(snapshot)
+    }
+
+    /// Every snapshotted hash, sorted by path.
+    pub fn hashes(&self) -> Vec<u64> {
+        let mut hashes: Vec<(&str, u64)> = self
+            .entries
+            .iter()
+            .map(|(hash, snapshot)| (snapshot.path.as_str(), *hash))
+            .collect();
+        hashes.sort();
+        hashes.into_iter().map(|(_, hash)| hash).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Bytes held by the snapshots.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_stay_within_the_budget() {
+        let mut store = SnapshotStore::new(8);
+        assert_eq!(store.record(1, "a", 0x100, &[0; 5]), Recorded::New);
+        assert_eq!(store.record(2, "b", 0x200, &[0; 4]), Recorded::OverBudget);
+        assert!(store.get(2).is_none());
+        assert_eq!(store.record(3, "c", 0x300, &[0; 3]), Recorded::New);
+        assert_eq!(store.used(), 8);
+
+        assert_eq!(store.remove(1).unwrap().data.len(), 5);
+        assert_eq!(store.used(), 3);
+        assert_eq!(store.record(2, "b", 0x200, &[0; 4]), Recorded::New);
+    }
+
+    #[test]
+    fn the_first_write_wins() {
+        let mut store = SnapshotStore::new(DEFAULT_BUDGET);
+        assert_eq!(store.record(1, "a", 0x100, &[1, 2]), Recorded::New);
+        assert_eq!(store.record(1, "a", 0x200, &[3, 4, 5]), Recorded::Existing);
+
+        let snapshot = store.get(1).unwrap();
+        assert_eq!(snapshot.data, [1, 2]);
+        assert_eq!(snapshot.data_ptr, 0x100);
+        assert_eq!(store.used(), 2);
+    }
+
+    #[test]
+    fn hashes_are_restored_in_path_order() {
+        let mut store = SnapshotStore::new(DEFAULT_BUDGET);
+        store.record(1, "ui/replace/b.bntx", 0, &[]);
+        store.record(2, "fighter/mario/model.numdlb", 0, &[]);
+        store.record(3, "ui/replace/a.bntx", 0, &[]);
+        assert_eq!(store.hashes(), [2, 3, 1]);
+        assert_eq!(store.len(), 3);
+    }
+}
@@ -0,0 +1,300 @@
+//! Load traces: every file the game loaded between `TRACE_START` and `TRACE_STOP`.
+//!
+//! The server sends one `load` line per entry,
+//! `load\t<milliseconds>\t<hash>\t<size>\t<source>\t<path>`, with the hash in hex and the path
+//! left empty when the server doesn't know it.
+
+use std::fmt;
+
+/// Entries past this are dropped, so a forgotten trace can't exhaust the console's memory.
+pub const MAX_ENTRIES: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceSource {
+    /// The threaded file loader finished loading the file
+    ThreadedLoad,
+    /// The file was queued in one of `ResServiceNX`'s res_lists
+    ResList,
+}
+
+impl TraceSource {
+    pub fn to_token(self) -> &'static str {
+        match self {
+            TraceSource::ThreadedLoad => "threaded",
+            TraceSource::ResList => "res_list",
+        }
+    }
+
+    pub fn parse_token(token: &str) -> Option<Self> {
+        match token {
+            "threaded" => Some(TraceSource::ThreadedLoad),
+            "res_list" => Some(TraceSource::ResList),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// Since the trace started
+    pub time_ms: u64,
+    pub hash: u64,
+    pub size: usize,
+    pub source: TraceSource,
+    pub path: Option<String>,
+}
+
+impl TraceEntry {
+    pub fn to_line(&self) -> String {
+        format!(
+            "load\t{}\t{:#x}\t{}\t{}\t{}",
+            self.time_ms,
+            self.hash,
+            self.size,
+            self.source.to_token(),
+            self.path.as_deref().unwrap_or("")
+        )
+    }
+
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let mut parts = line.trim_end_matches(&['\r', '\n'][..]).split('\t');
+        if parts.next()? != "load" {
+            return None;
+        }
+        let time_ms = parts.next()?.parse().ok()?;
+        let hash = u64::from_str_radix(parts.next()?.trim_start_matches("0x"), 16).ok()?;
+        let size = parts.next()?.parse().ok()?;
+        let source = TraceSource::parse_token(parts.next()?)?;
+        let path = parts
+            .next()
+            .filter(|path| !path.is_empty())
+            .map(String::from);
+        Some(Self {
+            time_ms,
+            hash,
+            size,
+            source,
+            path,
+        })
+    }
+
+    /// The path if known, the hash otherwise.
+    pub fn name(&self) -> String {
+        match &self.path {
+            Some(path) => path.clone(),
+            None => format!("{:#x}", self.hash),
+        }
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>8} ms  {:<8}  {:#x} bytes  {}",
+            self.time_ms,
+            self.source.to_token(),
+            self.size,
+            self.name()
+        )
+    }
+}
+
+pub fn to_response(entries: &[TraceEntry]) -> String {
+    let mut response = String::new();
+    for entry in entries {
+        response.push_str(&entry.to_line());
+        response.push('\n');
+    }
+    response
+}
+
+pub fn parse_response(response: &str) -> Vec<TraceEntry> {
+    response.lines().filter_map(TraceEntry::parse_line).collect()
+}
+
+/// Paths of the traced files in the order they were first loaded, without repeats.
+pub fn loaded_paths(entries: &[TraceEntry]) -> Vec<String> {
+    let mut paths: Vec<String> = vec![];
+    for path in entries.iter().filter_map(|entry| entry.path.as_ref()) {
+        if !paths.contains(path) {
+            paths.push(path.clone());
+        }
+    }
+    paths
+}
+
+pub fn to_csv(entries: &[TraceEntry]) -> String {
+    let mut csv = String::from("time_ms,hash,size,source,path\n");
+    for entry in entries {
+        csv.push_str(&format!(
+            "{},{:#x},{},{},{}\n",
+            entry.time_ms,
+            entry.hash,
+            entry.size,
+            entry.source.to_token(),
+            csv_field(entry.path.as_deref().unwrap_or(""))
+        ));
+    }
+    csv
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\r', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn to_json(entries: &[TraceEntry]) -> String {
+    let mut json = String::from("[\n");
+    for (i, entry) in entries.iter().enumerate() {
+        let path = match &entry.path {
+            Some(path) => json_string(path),
+            None => "null".to_string(),
+        };
+        json.push_str(&format!(
+            "  {{\"time_ms\": {}, \"hash\": \"{:#x}\", \"size\": {}, \"source\": \"{}\", \"path\": {}}}",
+            entry.time_ms,
+            entry.hash,
+            entry.size,
+            entry.source.to_token(),
+            path
+        ));
+        json.push_str(if i + 1 < entries.len() { ",\n" } else { "\n" });
+    }
+    json.push(']');
+    json.push('\n');
+    json
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(time_ms: u64, path: Option<&str>) -> TraceEntry {
+        TraceEntry {
+            time_ms,
+            hash: 0x1f_2e3d_4c5b,
+            size: 0x4000,
+            source: TraceSource::ThreadedLoad,
+            path: path.map(String::from),
+        }
+    }
+
+    /// Splits one CSV record back into its fields, undoing `csv_field`'s quoting.
+    fn csv_fields(record: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut chars = record.chars().peekable();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(String::new()),
+                c => fields.last_mut().unwrap().push(c),
+            }
+        }
+        fields
+    }
+
+    #[test]
+    fn entries_round_trip_through_the_response() {
+        let entries = vec![
+            entry(0, Some("ui/replace/chara/chara_0/chara_0_mario_00.bntx")),
+            TraceEntry {
+                source: TraceSource::ResList,
+                ..entry(16, None)
+            },
+        ];
+        let response = to_response(&entries);
+        assert_eq!(
+            response.lines().next(),
+            Some("load\t0\t0x1f2e3d4c5b\t16384\tthreaded\tui/replace/chara/chara_0/chara_0_mario_00.bntx")
+        );
+        assert_eq!(parse_response(&response), entries);
+        assert_eq!(entries[1].name(), "0x1f2e3d4c5b");
+    }
+
+    #[test]
+    fn loaded_paths_keep_first_load_order() {
+        let entries = vec![
+            entry(0, Some("ui/b")),
+            entry(1, None),
+            entry(2, Some("ui/a")),
+            entry(3, Some("ui/b")),
+        ];
+        assert_eq!(loaded_paths(&entries), ["ui/b", "ui/a"]);
+    }
+
+    #[test]
+    fn csv_quotes_fields_that_need_it() {
+        assert_eq!(csv_field("ui/a.bntx"), "ui/a.bntx");
+        assert_eq!(csv_field("ui/a,b.bntx"), "\"ui/a,b.bntx\"");
+        assert_eq!(csv_field("ui/\"a\".bntx"), "\"ui/\"\"a\"\".bntx\"");
+        assert_eq!(csv_field("ui/a\r.bntx"), "\"ui/a\r.bntx\"");
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        let entries = vec![entry(5, Some("ui/\"odd\", name.bntx")), entry(6, None)];
+        let csv = to_csv(&entries);
+        let mut records = csv.lines();
+        assert_eq!(records.next(), Some("time_ms,hash,size,source,path"));
+        assert_eq!(
+            csv_fields(records.next().unwrap()),
+            [
+                "5",
+                "0x1f2e3d4c5b",
+                "16384",
+                "threaded",
+                "ui/\"odd\", name.bntx"
+            ]
+        );
+        assert_eq!(
+            csv_fields(records.next().unwrap()),
+            ["6", "0x1f2e3d4c5b", "16384", "threaded", ""]
+        );
+        assert_eq!(records.next(), None);
+    }
+
+    #[test]
+    fn json_escapes_strings() {
+        assert_eq!(json_string("ui/a.bntx"), "\"ui/a.bntx\"");
+        assert_eq!(
+            json_string("a\"b\\c\nd\te\u{1}"),
+            "\"a\\\"b\\\\c\\nd\\te\\u0001\""
+        );
+    }
+
+    #[test]
+    fn json_lists_every_entry() {
+        let entries = vec![entry(5, Some("ui/\"a\".bntx")), entry(6, None)];
+        assert_eq!(
+            to_json(&entries),
+            "[\n  {\"time_ms\": 5, \"hash\": \"0x1f2e3d4c5b\", \"size\": 16384, \"source\": \"threaded\", \"path\": \"ui/\\\"a\\\".bntx\"},\n  {\"time_ms\": 6, \"hash\": \"0x1f2e3d4c5b\", \"size\": 16384, \"source\": \"threaded\", \"path\": null}\n]\n"
+        );
+        assert_eq!(to_json(&[]), "[\n]\n");
+    }
+}
@@ -0,0 +1,208 @@
+//! Bookkeeping for file buffers the game currently has loaded, fed by the server's load and
+//! free hooks.
+//!
+//! Entries are keyed by both the path hash and the buffer address. Once a buffer is freed the
+//! allocator may hand the same address to another file, so a load at a tracked address
+//! replaces whatever was recorded there before. The game keeps one buffer per file, so a load
+//! of a tracked hash replaces its older buffers as well, in case their free wasn't seen.
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoadedFile {
+    pub hash: u64,
+    pub data_ptr: u64,
+    pub size: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Tracker {
+    by_ptr: HashMap<u64, LoadedFile>,
+    /// Buffers holding each hash, oldest first
+    by_hash: HashMap<u64, Vec<u64>>,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a finished load. Returns the stale entries it displaced: the one at `data_ptr`
+    /// and any older buffer of `hash` whose free wasn't seen.
+    pub fn on_load(&mut self, hash: u64, data_ptr: u64, size: usize) -> Vec<LoadedFile> {
+        let mut displaced: Vec<LoadedFile> = self.remove_ptr(data_ptr).into_iter().collect();
+        for ptr in self.by_hash.get(&hash).cloned().unwrap_or_default() {
+            displaced.extend(self.remove_ptr(ptr));
+        }
+        self.by_ptr.insert(
+            data_ptr,
+            LoadedFile {
+                hash,
+                data_ptr,
+                size,
+            },
+        );
+        self.by_hash.entry(hash).or_default().push(data_ptr);
+        displaced
+    }
+
+    /// Forgets the buffer at `data_ptr` if it holds `hash`.
+    pub fn on_free(&mut self, hash: u64, data_ptr: u64) -> Option<LoadedFile> {
+        match self.by_ptr.get(&data_ptr) {
+            Some(file) if file.hash == hash => self.remove_ptr(data_ptr),
+            _ => None,
+        }
+    }
+
+    /// Forgets the buffer at `data_ptr` whatever it holds, for frees that carry no path.
+    pub fn on_free_ptr(&mut self, data_ptr: u64) -> Option<LoadedFile> {
+        self.remove_ptr(data_ptr)
+    }
+
+    fn remove_ptr(&mut self, data_ptr: u64) -> Option<LoadedFile> {
+        let file = self.by_ptr.remove(&data_ptr)?;
+        if let Some(ptrs) = self.by_hash.get_mut(&file.hash) {
+            ptrs.retain(|ptr| *ptr != data_ptr);
+            if ptrs.is_empty() {
+                self.by_hash.remove(&file.hash);
+            }
+        }
+        Some(file)
+    }
+
+    /// The most recently loaded buffer holding `hash`.
+    pub fn latest(&self, hash: u64) -> Option<LoadedFile> {
+        let ptr = self.by_hash.get(&hash)?.last()?;
+        self.by_ptr.get(ptr).copied()
+    }
+
+    /// Every buffer holding `hash`, oldest first.
+    pub fn get(&self, hash: u64) -> Vec<LoadedFile> {
+        self.by_hash
+            .get(&hash)
+            .map(|ptrs| ptrs.iter().filter_map(|ptr| self.by_ptr.get(ptr).copied()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn is_loaded(&self, hash: u64) -> bool {
+        self.by_hash.contains_key(&hash)
+    }
+
+    /// Number of tracked buffers.
+    pub fn len(&self) -> usize {
+        self.by_ptr.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_ptr.is_empty()
+    }
+
+    /// Number of distinct files among the tracked buffers.
+    pub fn file_count(&self) -> usize {
+        self.by_hash.len()
+    }
+
+    /// Every tracked buffer, sorted by hash then address.
+    pub fn entries(&self) -> Vec<LoadedFile> {
+        let mut entries: Vec<LoadedFile> = self.by_ptr.values().copied().collect();
+        entries.sort_by_key(|file| (file.hash, file.data_ptr));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARIO: u64 = 0x1234;
+    const LUIGI: u64 = 0x5678;
+
+    fn file(hash: u64, data_ptr: u64, size: usize) -> LoadedFile {
+        LoadedFile {
+            hash,
+            data_ptr,
+            size,
+        }
+    }
+
+    #[test]
+    fn load_then_free() {
+        let mut tracker = Tracker::new();
+        assert_eq!(tracker.on_load(MARIO, 0x1000, 0x80), vec![]);
+        assert!(tracker.is_loaded(MARIO));
+        assert_eq!(tracker.latest(MARIO), Some(file(MARIO, 0x1000, 0x80)));
+        assert_eq!((tracker.len(), tracker.file_count()), (1, 1));
+
+        assert_eq!(
+            tracker.on_free(MARIO, 0x1000),
+            Some(file(MARIO, 0x1000, 0x80))
+        );
+        assert!(!tracker.is_loaded(MARIO));
+        assert_eq!(tracker.latest(MARIO), None);
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.file_count(), 0);
+    }
+
+    #[test]
+    fn double_load_keeps_one_entry() {
+        let mut tracker = Tracker::new();
+        tracker.on_load(MARIO, 0x1000, 0x80);
+        assert_eq!(
+            tracker.on_load(MARIO, 0x1000, 0x80),
+            vec![file(MARIO, 0x1000, 0x80)]
+        );
+        assert_eq!(tracker.get(MARIO), vec![file(MARIO, 0x1000, 0x80)]);
+        assert_eq!(tracker.len(), 1);
+
+        tracker.on_free(MARIO, 0x1000);
+        assert!(tracker.is_empty());
+        assert!(!tracker.is_loaded(MARIO));
+    }
+
+    #[test]
+    fn freeing_an_untracked_buffer_changes_nothing() {
+        let mut tracker = Tracker::new();
+        tracker.on_load(MARIO, 0x1000, 0x80);
+        assert_eq!(tracker.on_free(MARIO, 0x2000), None);
+        assert_eq!(tracker.on_free_ptr(0x2000), None);
+        // The address is tracked, but for another file
+        assert_eq!(tracker.on_free(LUIGI, 0x1000), None);
+        assert_eq!(tracker.entries(), vec![file(MARIO, 0x1000, 0x80)]);
+    }
+
+    #[test]
+    fn free_without_a_path_goes_by_address() {
+        let mut tracker = Tracker::new();
+        tracker.on_load(MARIO, 0x1000, 0x80);
+        assert_eq!(tracker.on_free_ptr(0x1000), Some(file(MARIO, 0x1000, 0x80)));
+        assert!(!tracker.is_loaded(MARIO));
+    }
+
+    #[test]
+    fn reload_at_a_new_address_replaces_the_stale_entry() {
+        let mut tracker = Tracker::new();
+        tracker.on_load(MARIO, 0x1000, 0x80);
+        assert_eq!(
+            tracker.on_load(MARIO, 0x3000, 0x80),
+            vec![file(MARIO, 0x1000, 0x80)]
+        );
+        assert_eq!(tracker.get(MARIO), vec![file(MARIO, 0x3000, 0x80)]);
+        assert_eq!(tracker.latest(MARIO), Some(file(MARIO, 0x3000, 0x80)));
+        // The stale free arriving late doesn't drop the new buffer
+        assert_eq!(tracker.on_free(MARIO, 0x1000), None);
+        assert!(tracker.is_loaded(MARIO));
+    }
+
+    #[test]
+    fn reused_address_replaces_the_other_file() {
+        let mut tracker = Tracker::new();
+        tracker.on_load(MARIO, 0x1000, 0x80);
+        assert_eq!(
+            tracker.on_load(LUIGI, 0x1000, 0x40),
+            vec![file(MARIO, 0x1000, 0x80)]
+        );
+        assert!(!tracker.is_loaded(MARIO));
+        assert_eq!(tracker.latest(LUIGI), Some(file(LUIGI, 0x1000, 0x40)));
+        assert_eq!(tracker.entries(), vec![file(LUIGI, 0x1000, 0x40)]);
+    }
+}
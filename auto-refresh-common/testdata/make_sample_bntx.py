@@ -0,0 +1,202 @@
+#!/usr/bin/env python3
+"""Writes sample.bntx, a little endian BNTX laid out the way the game's UI textures are.
+
+The file has every block a real BNTX has: the BNTX and NX headers, the memory pool area,
+_STR, _DIC, one BRTI per texture, BRTD and _RLT. It holds a 64x64 BC1 texture with one mip
+and a 16x16 RGBA8 sRGB texture with two mips.
+
+    python3 make_sample_bntx.py
+"""
+
+import os
+import struct
+
+BASE_POOL_SIZE = 0x150
+BRTI_SIZE = 0xA0
+TEXTURE_OBJECT_SIZE = 0x100
+TEXTURE_VIEW_SIZE = 0x80
+BRTD_ALIGNMENT = 0x1000
+
+TEXTURES = [
+    # name, format, width, height, mip sizes
+    ("chara_0_sample_00", 0x1A01, 64, 64, [0x800]),
+    ("chara_1_sample_00", 0x0B06, 16, 16, [0x400, 0x100]),
+]
+
+
+def align(value, alignment):
+    return (value + alignment - 1) & ~(alignment - 1)
+
+
+class Writer:
+    def __init__(self):
+        self.data = bytearray()
+        self.pointers = []
+
+    def pad_to(self, offset):
+        assert offset >= len(self.data)
+        self.data.extend(bytes(offset - len(self.data)))
+
+    def put(self, offset, fmt, *values):
+        raw = struct.pack("<" + fmt, *values)
+        self.data[offset:offset + len(raw)] = raw
+
+    def pointer(self, offset, target):
+        """Writes a file offset the game relocates into an address."""
+        self.put(offset, "Q", target)
+        self.pointers.append(offset)
+
+
+def bit(name, index):
+    byte = index >> 3
+    if byte >= len(name):
+        return 0
+    return (name[len(name) - byte - 1] >> (index & 7)) & 1
+
+
+def first_difference(a, b):
+    for index in range(max(len(a), len(b)) * 8):
+        if bit(a, index) != bit(b, index):
+            return index
+    raise ValueError("duplicate name")
+
+
+def build_dict(names):
+    """Patricia trie nodes as [reference bit, left, right, name], the root first."""
+    nodes = [[-1, 0, 0, b""]]
+
+    def search(name, limit=None):
+        parent, child = 0, nodes[0][1]
+        while nodes[parent][0] < nodes[child][0] and (limit is None or nodes[child][0] < limit):
+            parent = child
+            child = nodes[child][2] if bit(name, nodes[child][0]) else nodes[child][1]
+        return parent, child
+
+    for name in names:
+        _, found = search(name)
+        reference = first_difference(name, nodes[found][3])
+        parent, child = search(name, reference)
+        index = len(nodes)
+        if bit(name, reference):
+            nodes.append([reference, child, index, name])
+        else:
+            nodes.append([reference, index, child, name])
+        if parent == 0 or not bit(name, nodes[parent][0]):
+            nodes[parent][1] = index
+        else:
+            nodes[parent][2] = index
+    return nodes
+
+
+def main():
+    w = Writer()
+    count = len(TEXTURES)
+    names = [name.encode() for name, *_ in TEXTURES]
+
+    w.pad_to(0x20)
+    w.pad_to(0x58)
+    pool = 0x58
+    w.pad_to(pool + BASE_POOL_SIZE)
+    info_pointers = len(w.data)
+    w.pad_to(info_pointers + count * 8)
+
+    # _STR: an empty string for the dictionary root, the file name, then the texture names
+    strings = align(len(w.data), 8)
+    w.pad_to(strings + 0x18)
+    string_offsets = {}
+    for text in [b"", b"sample"] + names:
+        string_offsets[text] = len(w.data)
+        w.data.extend(struct.pack("<H", len(text)) + text + b"\0")
+        w.pad_to(align(len(w.data), 2))
+    dic = align(len(w.data), 8)
+    w.pad_to(dic)
+    w.put(strings, "4sIII", b"_STR", dic - strings, dic - strings, 0)
+    w.put(strings + 0x10, "II", len(names) + 1, 0)
+
+    nodes = build_dict(names)
+    w.pad_to(dic + 8 + len(nodes) * 0x10)
+    w.put(dic, "4si", b"_DIC", count)
+    for i, (reference, left, right, name) in enumerate(nodes):
+        node = dic + 8 + i * 0x10
+        w.put(node, "IHH", reference & 0xFFFFFFFF, left, right)
+        w.pointer(node + 8, string_offsets[name])
+
+    blocks = []
+    for _, _, _, _, mip_sizes in TEXTURES:
+        block = align(len(w.data), 8)
+        mip_offsets = block + BRTI_SIZE
+        texture_object = align(mip_offsets + len(mip_sizes) * 8, 8)
+        texture_view = texture_object + TEXTURE_OBJECT_SIZE
+        w.pad_to(texture_view + TEXTURE_VIEW_SIZE)
+        blocks.append((block, mip_offsets, texture_object, texture_view))
+
+    brtd = align(len(w.data), BRTD_ALIGNMENT) - 0x10
+    w.pad_to(brtd + 0x10)
+    image_offsets = []
+    for index, (_, _, _, _, mip_sizes) in enumerate(TEXTURES):
+        start = align(len(w.data), 0x200)
+        w.pad_to(start)
+        offsets = []
+        for mip, size in enumerate(mip_sizes):
+            offsets.append(len(w.data))
+            w.data.extend(bytes((i * 7 + index * 31 + mip) & 0xFF for i in range(size)))
+        image_offsets.append(offsets)
+    rlt = align(len(w.data), 8)
+    w.pad_to(rlt)
+    w.put(brtd, "4sIQ", b"BRTD", 0, rlt - brtd)
+
+    for i, (name, fmt, width, height, mip_sizes) in enumerate(TEXTURES):
+        block, mip_offsets, texture_object, texture_view = blocks[i]
+        info = block + 0x10
+        next_block = blocks[i + 1][0] if i + 1 < count else brtd
+        w.pointer(info_pointers + i * 8, block)
+        w.put(block, "4sIII", b"BRTI", next_block - block, BRTI_SIZE, 0)
+        # flags, dimension, tile mode, swizzle, mip count, sample count
+        w.put(info, "BBHHHI", 1, 2, 0, 0, len(mip_sizes), 1)
+        # format, GPU access flags, width, height, depth, array layers, block height log2
+        w.put(info + 0x0C, "IIIIIII", fmt, 0x20, width, height, 1, 1, 4)
+        # image size, alignment, channel sources, image dimension
+        w.put(info + 0x40, "IIII", sum(mip_sizes), 0x200, 0x05040302, 1)
+        w.pointer(info + 0x50, string_offsets[name.encode()])
+        w.pointer(info + 0x58, 0x20)
+        w.pointer(info + 0x60, mip_offsets)
+        w.pointer(info + 0x70, texture_object)
+        w.pointer(info + 0x78, texture_view)
+        for mip, offset in enumerate(image_offsets[i]):
+            w.pointer(mip_offsets + mip * 8, offset)
+
+    w.put(0x20, "4sI", b"NX  ", count)
+    w.pointer(0x28, info_pointers)
+    w.pointer(0x30, brtd)
+    w.pointer(0x38, dic)
+    w.pointer(0x40, pool)
+    w.put(0x48, "II", BASE_POOL_SIZE, 0)
+
+    # _RLT: one section for everything before BRTD, one for the image data it points into.
+    # Each entry covers a run of adjacent pointers.
+    runs = []
+    for offset in sorted(w.pointers):
+        if runs and runs[-1][0] + runs[-1][1] * 8 == offset:
+            runs[-1][1] += 1
+        else:
+            runs.append([offset, 1])
+    sections = [(0, brtd, 0, len(runs)), (brtd, rlt - brtd, len(runs), 0)]
+    entries = rlt + 0x10 + len(sections) * 0x18
+    file_size = entries + len(runs) * 8
+    w.pad_to(file_size)
+    w.put(rlt, "4sIII", b"_RLT", rlt, len(sections), 0)
+    for i, (position, size, first, entry_count) in enumerate(sections):
+        w.put(rlt + 0x10 + i * 0x18, "QIIII", 0, position, size, first, entry_count)
+    for i, (offset, pointer_count) in enumerate(runs):
+        w.put(entries + i * 8, "IHBB", offset, 1, pointer_count, 0)
+
+    w.put(0, "4sII", b"BNTX", 0, 0x00040000)
+    w.put(0x0C, "HBBIHHII", 0xFEFF, 0x0C, 0x40, string_offsets[b"sample"] + 2, 0, strings, rlt, file_size)
+
+    path = os.path.join(os.path.dirname(os.path.abspath(__file__)), "sample.bntx")
+    with open(path, "wb") as f:
+        f.write(w.data)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,135 @@
+#ifndef AUTO_REFRESH_H
+#define AUTO_REFRESH_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Bumped whenever an export changes. Version 0 had no version export and returned `bool`
+ * from every function.
+ */
+#define AUTO_REFRESH_API_VERSION 1
+
+/**
+ * Outcome passed to refresh callbacks. Refreshes waiting for their file to load only fire
+ * once the load resolves them.
+ */
+enum AutoRefreshResult {
+  AUTO_REFRESH_RESULT_REFRESHED = 0,
+  /**
+   * Only part of the file could be refreshed, e.g. some textures of a BNTX
+   */
+  AUTO_REFRESH_RESULT_PARTIAL = 1,
+  AUTO_REFRESH_RESULT_SKIPPED = 2,
+};
+typedef uint32_t AutoRefreshResult;
+
+/**
+ * Called with the file's hash, its ARC path (or the hash in hex for files pushed by hash),
+ * the result and the `user_data` given when registering. The path is only valid for the
+ * duration of the call.
+ */
+typedef void (*AutoRefreshCallback)(uint64_t hash,
+                                    const char *path,
+                                    AutoRefreshResult result,
+                                    void *user_data);
+
+/**
+ * Returned by every export other than `auto_refresh_api_version`.
+ */
+enum AutoRefreshStatus {
+  AUTO_REFRESH_STATUS_OK = 0,
+  /**
+   * Only some textures of the BNTX were refreshed, the log has the reason
+   */
+  AUTO_REFRESH_STATUS_PARTIAL = 1,
+  /**
+   * A pointer was null or a size was 0
+   */
+  AUTO_REFRESH_STATUS_INVALID_ARGUMENT = -1,
+  /**
+   * This game version isn't supported, or the BNTX hooks aren't installed
+   */
+  AUTO_REFRESH_STATUS_UNSUPPORTED = -2,
+  /**
+   * The game doesn't have the file loaded
+   */
+  AUTO_REFRESH_STATUS_NOT_LOADED = -3,
+  /**
+   * The hash isn't in the ARC
+   */
+  AUTO_REFRESH_STATUS_NOT_FOUND = -4,
+  /**
+   * The refresh failed, the log has the reason
+   */
+  AUTO_REFRESH_STATUS_FAILED = -5,
+  AUTO_REFRESH_STATUS_ALREADY_REGISTERED = -6,
+  AUTO_REFRESH_STATUS_NOT_REGISTERED = -7,
+  /**
+   * auto-refresh can't serve the call anymore, a worker stopped or a lock was poisoned
+   */
+  AUTO_REFRESH_STATUS_INTERNAL = -8,
+};
+typedef int32_t AutoRefreshStatus;
+
+/**
+ * Filled in by `auto_refresh_query`.
+ */
+typedef struct AutoRefreshFileInfo {
+  bool loaded;
+  /**
+   * Size of the loaded buffer, 0 if the file isn't loaded
+   */
+  uint64_t loaded_size;
+  /**
+   * An enabled mod folder provides the file, as of the last scan
+   */
+  bool in_mods;
+  /**
+   * A refresh is waiting for the game to load the file
+   */
+  bool pending;
+  /**
+   * The contents from before the first refresh are kept and can be restored
+   */
+  bool snapshotted;
+} AutoRefreshFileInfo;
+
+uint32_t auto_refresh_api_version(void);
+
+/**
+ * Replaces the loaded textures of the BNTX `hash` with the ones in `replace`.
+ */
+AutoRefreshStatus auto_refresh_bntx(uint64_t hash, const uint8_t *replace, size_t size);
+
+/**
+ * Overwrites the loaded file `hash` with `replace`, which has to match its size.
+ */
+AutoRefreshStatus auto_refresh_file(uint64_t hash, const uint8_t *replace, size_t size);
+
+/**
+ * Fills `info` with what auto-refresh knows about `hash`.
+ */
+AutoRefreshStatus auto_refresh_query(uint64_t hash, struct AutoRefreshFileInfo *info);
+
+/**
+ * Rebuilds the index of the mod folders. Blocks while the SD card is scanned.
+ */
+AutoRefreshStatus auto_refresh_rescan(void);
+
+/**
+ * Calls `callback` after every refresh, on a thread of its own.
+ */
+AutoRefreshStatus auto_refresh_register_callback(AutoRefreshCallback callback, void *user_data);
+
+/**
+ * Takes the same `callback` and `user_data` that were registered.
+ */
+AutoRefreshStatus auto_refresh_unregister_callback(AutoRefreshCallback callback,
+                                                   void *user_data);
+
+#endif /* AUTO_REFRESH_H */
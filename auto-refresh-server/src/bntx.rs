@@ -1,19 +1,15 @@
+use auto_refresh_common::bntx as parser;
+use auto_refresh_common::protocol::FileResult;
+use auto_refresh_common::snapshot::Snapshot;
+use auto_refresh_common::tracker::{LoadedFile, Tracker};
 use once_cell::sync::Lazy;
-use skyline::hooks::InlineCtx;
-use skyline::{hook, install_hooks};
+use skyline::hooks::{A64InlineHook, InlineCtx};
 use smash_arc::*;
-use std::collections::HashMap;
-use std::convert::TryInto;
-use std::ffi::CStr;
+use std::ffi::{c_void, CStr};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 
-use crate::resource;
-
-struct HelperThreadedFileInfo {
-    is_loaded: bool,
-    data_ptr: u64,
-    decompressed_size: usize,
-}
+use crate::{offsets, pending, resource, snapshot, trace, RefreshError};
 
 #[repr(packed)]
 struct ThreadedFileLoad {
@@ -24,54 +20,163 @@ struct ThreadedFileLoad {
     // and more...
 }
 
-static THREADED_FILES: Lazy<Mutex<HashMap<u64, HelperThreadedFileInfo>>> = Lazy::new(|| {
-    let mut m = HashMap::new();
-    Mutex::new(m)
-});
-
-fn check_size(loaded_bntx: &[u8], replace: &[u8]) -> Option<usize> {
-    if loaded_bntx.len() > 0x1000 && replace.len() > 0x1000 {
-        let loaded_image_size = usize::from_le_bytes(loaded_bntx[0xFF8..0x1000].try_into().ok()?);
-        let replace_image_size = usize::from_le_bytes(replace[0xFF8..0x1000].try_into().ok()?);
-        if loaded_image_size == replace_image_size {
-            return Some(loaded_image_size);
+static BNTX_REFRESH_ENABLED: AtomicBool = AtomicBool::new(false);
+
+static THREADED_FILES: Lazy<Mutex<Tracker>> = Lazy::new(|| Mutex::new(Tracker::new()));
+
+/// Snapshot of the tracked buffers, for diagnostics.
+pub fn tracked_files() -> Vec<LoadedFile> {
+    THREADED_FILES.lock().unwrap().entries()
+}
+
+/// Outcome of a BNTX refresh for each texture in the replacement file.
+#[derive(Debug, Default)]
+pub struct ReplaceReport {
+    pub refreshed: Vec<String>,
+    /// Texture name and why it was left untouched
+    pub rejected: Vec<(String, String)>,
+}
+
+impl ReplaceReport {
+    pub fn to_result(&self, path: &str) -> FileResult {
+        if self.rejected.is_empty() {
+            FileResult::refreshed(path)
+        } else if self.refreshed.is_empty() {
+            FileResult::skipped(path, self.describe_rejected())
+        } else {
+            FileResult::partial(path, self.describe_rejected())
         }
-        return None;
     }
-    return None;
+
+    pub fn describe_rejected(&self) -> String {
+        self.rejected
+            .iter()
+            .map(|(name, reason)| format!("{}: {}", name, reason))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// Copies the textures of `replace` into the loaded file, snapshotting it first.
+pub fn handle_file_replace(
+    path: &str,
+    hash: Hash40,
+    replace: &[u8],
+) -> Result<ReplaceReport, RefreshError> {
+    replace_textures(path, hash, replace, 0, true)
+}
+
+/// Copies the textures of a snapshot back into the loaded file.
+pub fn restore(hash: Hash40, original: &Snapshot) -> Result<ReplaceReport, RefreshError> {
+    replace_textures(&original.path, hash, &original.data, original.data_ptr, false)
 }
 
-pub fn handle_file_replace(hash: Hash40, replace: &[u8]) -> bool {
+/// `replace_base` is the address `replace` was relocated against, 0 for a file from the SD.
+fn replace_textures(
+    path: &str,
+    hash: Hash40,
+    replace: &[u8],
+    replace_base: u64,
+    take_snapshot: bool,
+) -> Result<ReplaceReport, RefreshError> {
+    if !is_enabled() {
+        return Err("bntx refresh is disabled for this game version".to_string().into());
+    }
+
     let map = THREADED_FILES.lock().unwrap();
-    if map.contains_key(&hash.as_u64()) {
-        let loaded_image = map.get(&hash.as_u64()).unwrap();
-        if loaded_image.is_loaded {
-            let loaded_image_slice = unsafe {
-                std::slice::from_raw_parts_mut(
-                    loaded_image.data_ptr as *mut u8,
-                    loaded_image.decompressed_size,
-                )
-            };
-            if let Some(loaded_image_size) = check_size(loaded_image_slice, replace) {
-                loaded_image_slice[0x1000..loaded_image_size + 0x1000]
-                    .copy_from_slice(&replace[0x1000..loaded_image_size + 0x1000]);
-                return true;
+    let loaded_image = match map.latest(hash.as_u64()) {
+        Some(loaded_image) => loaded_image,
+        None => {
+            log!(
+                Info,
+                Bntx,
+                "Bntx file: {:#X} is not currently loaded.",
+                hash.as_u64()
+            );
+            return Err(RefreshError::NotLoaded);
+        }
+    };
+
+    let loaded_image_slice = unsafe {
+        std::slice::from_raw_parts_mut(
+            loaded_image.data_ptr as *mut u8,
+            loaded_image.size,
+        )
+    };
+    let loaded = parser::parse(loaded_image_slice, loaded_image.data_ptr).map_err(|err| {
+        log!(
+            Warn,
+            Bntx,
+            "Bntx file: {:#X} could not be read from memory: {}",
+            hash.as_u64(),
+            err
+        );
+        format!("loaded bntx could not be read: {}", err)
+    })?;
+    let replacement = parser::parse(replace, replace_base).map_err(|err| {
+        log!(
+            Warn,
+            Bntx,
+            "Bntx file: {:#X} replacement is not a valid bntx: {}",
+            hash.as_u64(),
+            err
+        );
+        format!("not a valid bntx: {}", err)
+    })?;
+
+    // Taken right before the first texture is copied, so a file nothing is written to isn't
+    // snapshotted
+    let mut needs_snapshot = take_snapshot;
+    let mut report = ReplaceReport::default();
+    for (i, replace_texture) in replacement.textures.iter().enumerate() {
+        let name = replace_texture.display_name(i);
+        let loaded_texture = match loaded.find_match(i, replace_texture) {
+            Some(loaded_texture) => loaded_texture,
+            None => {
+                log!(
+                    Warn,
+                    Bntx,
+                    "Bntx file: {:#X} texture {} is not in the loaded file.",
+                    hash.as_u64(),
+                    name
+                );
+                report.rejected.push((name, "not in the loaded file".to_string()));
+                continue;
             }
-            println!("[auto-refresh] Bntx file: {:#X} does not match loaded size, so the refresh request was rejected.", hash.as_u64());
-            return false;
+        };
+
+        let mismatches = loaded_texture.compare(replace_texture);
+        if !mismatches.is_empty() {
+            let reason = mismatches
+                .iter()
+                .map(|mismatch| mismatch.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            log!(
+                Warn,
+                Bntx,
+                "Bntx file: {:#X} texture {} was rejected: {}",
+                hash.as_u64(),
+                name,
+                reason
+            );
+            report.rejected.push((name, reason));
+            continue;
         }
-    }
 
-    println!(
-        "[auto-refresh] Bntx file: {:#X} is not currently loaded.",
-        hash.as_u64()
-    );
+        if needs_snapshot {
+            snapshot::record(hash.as_u64(), path, loaded_image_slice);
+            needs_snapshot = false;
+        }
+        loaded_image_slice[loaded_texture.data_range()]
+            .copy_from_slice(&replace[replace_texture.data_range()]);
+        report.refreshed.push(name);
+    }
 
-    return false;
+    Ok(report)
 }
 
-#[hook(offset = 0x37a17ac, inline)]
-unsafe fn load_files_threaded_hook(ctx: &mut InlineCtx) {
+unsafe extern "C" fn load_files_threaded_hook(ctx: &mut InlineCtx) {
     let threaded_load = *ctx.registers[19].x.as_ref() as *mut ThreadedFileLoad;
 
     let file_path_index = (*threaded_load).file_path_index as usize;
@@ -84,44 +189,64 @@ unsafe fn load_files_threaded_hook(ctx: &mut InlineCtx) {
 
         let decompressed_size = (*threaded_load).decompressed_size;
 
-        THREADED_FILES.lock().unwrap().insert(
+        THREADED_FILES.lock().unwrap().on_load(
             path_hash.as_u64(),
-            HelperThreadedFileInfo {
-                is_loaded: true,
-                data_ptr: (*threaded_load).data_ptr as u64,
-                decompressed_size: decompressed_size,
-            },
+            (*threaded_load).data_ptr as u64,
+            decompressed_size,
         );
+        trace::on_threaded_load(path_hash.as_u64(), decompressed_size);
+        pending::on_load(path_hash.as_u64());
     }
 }
 
-#[hook(offset = 0x37a1470, inline)]
-unsafe fn free_files_threaded_hook(ctx: &mut InlineCtx) {
+unsafe extern "C" fn free_files_threaded_hook(ctx: &mut InlineCtx) {
     let threaded_load = *ctx.registers[0].x.as_ref() as *mut ThreadedFileLoad;
 
     let file_path_index = (*threaded_load).file_path_index as usize;
 
     let arc = resource::arc();
 
+    let data_ptr = (*threaded_load).data_ptr as u64;
+
     let mut map = THREADED_FILES.lock().unwrap();
 
     if file_path_index < arc.get_file_paths().len() {
         let file_path = &arc.get_file_paths()[file_path_index];
         let path_hash = file_path.path.hash40();
 
-        if map.contains_key(&path_hash.as_u64()) {
-            map.entry(path_hash.as_u64())
-                .and_modify(|helper| (*helper).is_loaded = false);
-        }
+        map.on_free(path_hash.as_u64(), data_ptr);
     } else if file_path_index == 0xFFFFFF {
-        for (hash, load) in map.iter_mut() {
-            if load.data_ptr == (*threaded_load).data_ptr as u64 {
-                load.is_loaded = false;
-            }
-        }
+        map.on_free_ptr(data_ptr);
     }
 }
 
+pub fn is_enabled() -> bool {
+    BNTX_REFRESH_ENABLED.load(Ordering::SeqCst)
+}
+
+/// Hooks the threaded load/free sites found by `offsets`. If either signature is missing the
+/// hooks would land on the wrong instruction, so BNTX refresh is disabled instead.
 pub fn install() {
-    install_hooks!(load_files_threaded_hook, free_files_threaded_hook);
+    match (offsets::load_files_threaded(), offsets::free_files_threaded()) {
+        _ if !offsets::is_supported() => {
+            log!(Warn, Bntx, "Filesystem offsets not found, bntx refresh is disabled.")
+        }
+        (Some(load_offset), Some(free_offset)) => unsafe {
+            A64InlineHook(
+                offsets::offset_to_addr(load_offset) as *const c_void,
+                load_files_threaded_hook as *const c_void,
+            );
+            A64InlineHook(
+                offsets::offset_to_addr(free_offset) as *const c_void,
+                free_files_threaded_hook as *const c_void,
+            );
+            BNTX_REFRESH_ENABLED.store(true, Ordering::SeqCst);
+        },
+        (load_offset, free_offset) => log!(
+            Warn,
+            Bntx,
+            "Bntx hook signatures not found (load: {:?}, free: {:?}), bntx refresh is disabled.",
+            load_offset, free_offset
+        ),
+    }
 }
@@ -0,0 +1,178 @@
+use auto_refresh_common::log::Level;
+use auto_refresh_common::{overlay, pending, protocol, snapshot};
+use once_cell::sync::Lazy;
+use std::net::IpAddr;
+use std::time::Duration;
+
+const CONFIG_PATH: &str = "sd:/ultimate/auto-refresh/config.txt";
+const DEFAULT_NICKNAME: &str = "Nintendo Switch";
+
+pub static CONFIG: Lazy<Config> = Lazy::new(Config::load);
+
+/// Server settings, read once from `CONFIG_PATH`. Each line is `key = value`, `#` starts a comment.
+///
+/// ```text
+/// # Only these clients may connect. Leave empty to allow everyone.
+/// allowlist = 192.168.1.20, 192.168.1.21
+/// # Clients must send this token in their HELLO line.
+/// token = hunter2
+/// # Shown to clients looking for consoles on the network.
+/// nickname = Living room switch
+/// # Memory kept for restoring refreshed files to their original contents, in MiB.
+/// snapshot_budget_mb = 64
+/// # Memory kept for files pushed into the overlay, in MiB.
+/// overlay_budget_mb = 64
+/// # Memory kept for refreshes waiting for the game to load their file, in MiB.
+/// pending_budget_mb = 64
+/// # Lowest level logged: debug, info, warn or error.
+/// log_level = info
+/// # Also append the log to this file.
+/// log_file = sd:/ultimate/auto-refresh/log.txt
+/// # Check the indexed mod files for changes this often, in seconds, and refresh them.
+/// # Off when 0 or missing, for setups where nothing runs the client.
+/// poll_interval_s = 5
+/// # Files the polling leaves alone. `*` matches any run of characters, `?` a single one.
+/// poll_exclude = *.nus3audio, ui/message/*
+/// ```
+#[derive(Debug)]
+pub struct Config {
+    pub allowlist: Vec<IpAddr>,
+    pub token: Option<String>,
+    pub nickname: String,
+    /// In bytes
+    pub snapshot_budget: usize,
+    /// In bytes
+    pub overlay_budget: usize,
+    /// In bytes
+    pub pending_budget: usize,
+    pub log_level: Level,
+    pub log_file: Option<String>,
+    pub poll_interval: Option<Duration>,
+    /// ARC path patterns
+    pub poll_exclusions: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            allowlist: vec![],
+            token: None,
+            nickname: DEFAULT_NICKNAME.to_string(),
+            snapshot_budget: snapshot::DEFAULT_BUDGET,
+            overlay_budget: overlay::DEFAULT_BUDGET,
+            pending_budget: pending::DEFAULT_BUDGET,
+            log_level: Level::Info,
+            log_file: None,
+            poll_interval: None,
+            poll_exclusions: vec![],
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Self {
+        match std::fs::read_to_string(CONFIG_PATH) {
+            Ok(contents) => Self::parse(&contents),
+            Err(_) => {
+                log!(
+                    Info,
+                    Config,
+                    "No config found at {}, using defaults.",
+                    CONFIG_PATH
+                );
+                Self::default()
+            }
+        }
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut config = Self::default();
+        for line in contents.lines() {
+            let line = line.splitn(2, '#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().unwrap_or("").trim();
+            match key {
+                "allowlist" => {
+                    for address in value.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+                        match address.parse() {
+                            Ok(address) => config.allowlist.push(address),
+                            Err(_) => {
+                                log!(Warn, Config, "Config: {} is not a valid address.", address)
+                            }
+                        }
+                    }
+                }
+                "token" if !value.is_empty() => config.token = Some(value.to_string()),
+                "token" => {}
+                "nickname" if !value.is_empty() => config.nickname = value.to_string(),
+                "nickname" => {}
+                "snapshot_budget_mb" => match value.parse::<usize>() {
+                    Ok(megabytes) => config.snapshot_budget = megabytes * 1024 * 1024,
+                    Err(_) => log!(
+                        Warn,
+                        Config,
+                        "Config: {} is not a valid snapshot budget.",
+                        value
+                    ),
+                },
+                "overlay_budget_mb" => match value.parse::<usize>() {
+                    Ok(megabytes) => config.overlay_budget = megabytes * 1024 * 1024,
+                    Err(_) => log!(
+                        Warn,
+                        Config,
+                        "Config: {} is not a valid overlay budget.",
+                        value
+                    ),
+                },
+                "pending_budget_mb" => match value.parse::<usize>() {
+                    Ok(megabytes) => config.pending_budget = megabytes * 1024 * 1024,
+                    Err(_) => log!(
+                        Warn,
+                        Config,
+                        "Config: {} is not a valid pending budget.",
+                        value
+                    ),
+                },
+                "log_level" => match Level::parse_token(value) {
+                    Some(level) => config.log_level = level,
+                    None => log!(Warn, Config, "Config: {} is not a log level.", value),
+                },
+                "log_file" if !value.is_empty() => config.log_file = Some(value.to_string()),
+                "log_file" => {}
+                "poll_interval_s" => match value.parse::<u64>() {
+                    Ok(0) => config.poll_interval = None,
+                    Ok(seconds) => config.poll_interval = Some(Duration::from_secs(seconds)),
+                    Err(_) => log!(
+                        Warn,
+                        Config,
+                        "Config: {} is not a valid poll interval.",
+                        value
+                    ),
+                },
+                "poll_exclude" => config.poll_exclusions.extend(
+                    value
+                        .split(',')
+                        .map(|pattern| pattern.trim().replace('\\', "/").to_lowercase())
+                        .filter(|pattern| !pattern.is_empty()),
+                ),
+                _ => log!(Warn, Config, "Config: unknown key {}.", key),
+            }
+        }
+        config
+    }
+
+    pub fn is_allowed(&self, address: &IpAddr) -> bool {
+        self.allowlist.is_empty() || self.allowlist.contains(address)
+    }
+
+    pub fn check_token(&self, token: Option<&str>) -> bool {
+        match &self.token {
+            Some(expected) => token.map_or(false, |token| protocol::token_matches(expected, token)),
+            None => true,
+        }
+    }
+}
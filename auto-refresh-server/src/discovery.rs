@@ -0,0 +1,136 @@
+use auto_refresh_common::backoff::{
+    Backoff, ACCEPT_BACKOFF, MAX_ACCEPT_BACKOFF, MAX_BIND_BACKOFF, REBIND_AFTER_FAILURES,
+};
+use auto_refresh_common::discovery::{self, Announcement, DISCOVERY_PORT, MAX_DATAGRAM};
+use auto_refresh_common::protocol::DEFAULT_PORT;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use crate::config;
+
+static ANSWERING: AtomicBool = AtomicBool::new(false);
+
+/// Starts answering discovery broadcasts from clients.
+pub fn start() {
+    let answerer = thread::Builder::new()
+        .name("auto-refresh-discovery".to_string())
+        .spawn(serve);
+    if let Err(err) = answerer {
+        log!(
+            Error,
+            Network,
+            "Failed to spawn the discovery thread: {:?}",
+            err
+        );
+    }
+}
+
+/// Binds the discovery port, binding it again whenever receiving keeps failing.
+fn serve() {
+    let mut bind_backoff = Backoff::new(ACCEPT_BACKOFF, MAX_BIND_BACKOFF);
+    loop {
+        match UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT)) {
+            Ok(socket) => {
+                bind_backoff.succeed();
+                ANSWERING.store(true, Ordering::SeqCst);
+                answer(&socket);
+                ANSWERING.store(false, Ordering::SeqCst);
+                log!(
+                    Warn,
+                    Network,
+                    "Receiving discovery broadcasts keeps failing, binding port {} again.",
+                    DISCOVERY_PORT
+                );
+            }
+            Err(err) => {
+                let delay = bind_backoff.fail();
+                log!(
+                    Error,
+                    Network,
+                    "Failed to bind discovery port {}: {:?}, retrying in {}ms",
+                    DISCOVERY_PORT,
+                    err,
+                    delay.as_millis()
+                );
+                thread::sleep(delay);
+            }
+        }
+    }
+}
+
+/// Answers broadcasts until receiving fails `REBIND_AFTER_FAILURES` times in a row.
+fn answer(socket: &UdpSocket) {
+    let mut backoff = Backoff::new(ACCEPT_BACKOFF, MAX_ACCEPT_BACKOFF);
+    let mut buffer = [0; MAX_DATAGRAM];
+    loop {
+        let (size, source) = match socket.recv_from(&mut buffer) {
+            Ok(received) => {
+                backoff.succeed();
+                received
+            }
+            Err(err) => {
+                log!(Warn, Network, "Discovery receive error: {:?}", err);
+                let delay = backoff.fail();
+                if backoff.failures() >= REBIND_AFTER_FAILURES {
+                    return;
+                }
+                thread::sleep(delay);
+                continue;
+            }
+        };
+        if !discovery::is_discover_request(&buffer[..size]) {
+            continue;
+        }
+        if !config::CONFIG.is_allowed(&source.ip()) {
+            log!(
+                Debug,
+                Network,
+                "Ignored discovery from {}: not in the allowlist.",
+                source
+            );
+            continue;
+        }
+        let announcement = Announcement {
+            ip: local_ip(source),
+            port: DEFAULT_PORT,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            nickname: config::CONFIG.nickname.clone(),
+        };
+        match socket.send_to(announcement.to_line().as_bytes(), source) {
+            Ok(_) => log!(Debug, Network, "Answered discovery from {}.", source),
+            Err(err) => log!(
+                Warn,
+                Network,
+                "Failed to answer discovery from {}: {:?}",
+                source,
+                err
+            ),
+        }
+    }
+}
+
+/// The console's address on the interface that reaches `peer`. Unspecified if it can't be
+/// told, clients then use the address the answer came from.
+fn local_ip(peer: SocketAddr) -> IpAddr {
+    UdpSocket::bind(("0.0.0.0", 0))
+        .and_then(|socket| {
+            socket.connect(peer)?;
+            socket.local_addr()
+        })
+        .map(|address| address.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+/// For `STATUS`.
+pub fn describe() -> String {
+    if ANSWERING.load(Ordering::SeqCst) {
+        format!(
+            "answering on port {} as \"{}\"",
+            DISCOVERY_PORT,
+            config::CONFIG.nickname
+        )
+    } else {
+        "off".to_string()
+    }
+}
@@ -0,0 +1,161 @@
+use auto_refresh_common::protocol::{FileResult, FileStatus};
+use once_cell::sync::Lazy;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+/// Outcome passed to refresh callbacks. Refreshes waiting for their file to load only fire
+/// once the load resolves them.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoRefreshResult {
+    Refreshed = 0,
+    /// Only part of the file could be refreshed, e.g. some textures of a BNTX
+    Partial = 1,
+    Skipped = 2,
+}
+
+/// Called with the file's hash, its ARC path (or the hash in hex for files pushed by hash),
+/// the result and the `user_data` given when registering. The path is only valid for the
+/// duration of the call.
+pub type AutoRefreshCallback = unsafe extern "C" fn(
+    hash: u64,
+    path: *const c_char,
+    result: AutoRefreshResult,
+    user_data: *mut c_void,
+);
+
+#[derive(Clone, Copy)]
+struct Registration {
+    callback: AutoRefreshCallback,
+    user_data: *mut c_void,
+}
+
+// `user_data` is only ever handed back to the plugin that registered it
+unsafe impl Send for Registration {}
+
+impl Registration {
+    fn matches(&self, callback: AutoRefreshCallback, user_data: *mut c_void) -> bool {
+        self.callback as usize == callback as usize && self.user_data == user_data
+    }
+}
+
+struct Event {
+    hash: u64,
+    path: String,
+    result: AutoRefreshResult,
+}
+
+static CALLBACKS: Lazy<Mutex<Vec<Registration>>> = Lazy::new(|| Mutex::new(vec![]));
+
+/// Callbacks run on a thread of their own, so they can push files themselves without waiting
+/// on the refresh that fired them.
+/// `None` if the thread couldn't be started, callbacks then never fire.
+static EVENTS: Lazy<Option<Mutex<Sender<Event>>>> = Lazy::new(|| {
+    let (sender, receiver) = channel();
+    let spawned = thread::Builder::new()
+        .name("auto-refresh-events".to_string())
+        .spawn(move || dispatch(receiver));
+    match spawned {
+        Ok(_) => Some(Mutex::new(sender)),
+        Err(err) => {
+            log!(
+                Error,
+                Refresh,
+                "Failed to spawn the refresh event thread: {}",
+                err
+            );
+            None
+        }
+    }
+});
+
+/// Returns false if the pair is already registered, `None` if the callbacks can't be read.
+pub fn register(callback: AutoRefreshCallback, user_data: *mut c_void) -> Option<bool> {
+    let mut callbacks = CALLBACKS.lock().ok()?;
+    if callbacks
+        .iter()
+        .any(|registration| registration.matches(callback, user_data))
+    {
+        return Some(false);
+    }
+    callbacks.push(Registration {
+        callback,
+        user_data,
+    });
+    log!(
+        Info,
+        Refresh,
+        "Registered refresh callback {:#x}.",
+        callback as usize
+    );
+    Some(true)
+}
+
+/// Returns false if the pair wasn't registered, `None` if the callbacks can't be read.
+pub fn unregister(callback: AutoRefreshCallback, user_data: *mut c_void) -> Option<bool> {
+    let mut callbacks = CALLBACKS.lock().ok()?;
+    let count = callbacks.len();
+    callbacks.retain(|registration| !registration.matches(callback, user_data));
+    if callbacks.len() == count {
+        return Some(false);
+    }
+    log!(
+        Info,
+        Refresh,
+        "Unregistered refresh callback {:#x}.",
+        callback as usize
+    );
+    Some(true)
+}
+
+/// Fires the callbacks for a finished refresh. Pending results are left for the load hook to
+/// report once it resolves them, and stream files the game still has to re-open don't fire.
+pub fn notify(hash: u64, result: &FileResult) {
+    let outcome = match result.status {
+        FileStatus::Refreshed => AutoRefreshResult::Refreshed,
+        FileStatus::Partial(_) => AutoRefreshResult::Partial,
+        FileStatus::Pending(_) | FileStatus::Reopen(_) => return,
+        FileStatus::Skipped(_) => AutoRefreshResult::Skipped,
+    };
+    if CALLBACKS
+        .lock()
+        .map_or(true, |callbacks| callbacks.is_empty())
+    {
+        return;
+    }
+    if let Some(Ok(events)) = EVENTS.as_ref().map(Mutex::lock) {
+        let _ = events.send(Event {
+            hash,
+            path: result.path.clone(),
+            result: outcome,
+        });
+    }
+}
+
+fn dispatch(events: Receiver<Event>) {
+    for event in events {
+        let path = CString::new(event.path).unwrap_or_default();
+        // Copied so a callback can unregister itself
+        let callbacks = match CALLBACKS.lock() {
+            Ok(callbacks) => callbacks.clone(),
+            Err(_) => return,
+        };
+        for registration in callbacks {
+            unsafe {
+                (registration.callback)(
+                    event.hash,
+                    path.as_ptr(),
+                    event.result,
+                    registration.user_data,
+                )
+            };
+        }
+    }
+}
+
+pub fn count() -> usize {
+    CALLBACKS.lock().unwrap().len()
+}
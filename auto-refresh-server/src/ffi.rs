@@ -1,37 +1,235 @@
+use auto_refresh_common::protocol::FileResult;
 use smash_arc::*;
+use std::os::raw::c_void;
+
+use crate::events::{self, AutoRefreshCallback};
+use crate::{bntx, index, offsets, pending, queue, snapshot, RefreshError};
+
+/// Bumped whenever an export changes. Version 0 had no version export and returned `bool`
+/// from every function.
+pub const AUTO_REFRESH_API_VERSION: u32 = 1;
+
+/// Returned by every export other than `auto_refresh_api_version`.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoRefreshStatus {
+    Ok = 0,
+    /// Only some textures of the BNTX were refreshed, the log has the reason
+    Partial = 1,
+    /// A pointer was null or a size was 0
+    InvalidArgument = -1,
+    /// This game version isn't supported, or the BNTX hooks aren't installed
+    Unsupported = -2,
+    /// The game doesn't have the file loaded
+    NotLoaded = -3,
+    /// The hash isn't in the ARC
+    NotFound = -4,
+    /// The refresh failed, the log has the reason
+    Failed = -5,
+    AlreadyRegistered = -6,
+    NotRegistered = -7,
+    /// auto-refresh can't serve the call anymore, a worker stopped or a lock was poisoned
+    Internal = -8,
+}
+
+impl From<&RefreshError> for AutoRefreshStatus {
+    fn from(err: &RefreshError) -> Self {
+        match err {
+            RefreshError::NotLoaded => AutoRefreshStatus::NotLoaded,
+            RefreshError::NotFound => AutoRefreshStatus::NotFound,
+            RefreshError::Failed(_) => AutoRefreshStatus::Failed,
+        }
+    }
+}
+
+/// Filled in by `auto_refresh_query`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AutoRefreshFileInfo {
+    pub loaded: bool,
+    /// Size of the loaded buffer, 0 if the file isn't loaded
+    pub loaded_size: u64,
+    /// An enabled mod folder provides the file, as of the last scan
+    pub in_mods: bool,
+    /// A refresh is waiting for the game to load the file
+    pub pending: bool,
+    /// The contents from before the first refresh are kept and can be restored
+    pub snapshotted: bool,
+}
+
+/// Runs `job` on the refresh worker, or gives the status to return if the worker stopped.
+fn apply<R, F>(job: F) -> Result<R, AutoRefreshStatus>
+where
+    R: Send + 'static,
+    F: FnOnce() -> R + Send + 'static,
+{
+    queue::apply(job).map_err(|err| {
+        log!(Error, Refresh, "{}", err);
+        AutoRefreshStatus::Internal
+    })
+}
+
+/// Copies the caller's buffer, or `None` if it can't be read.
+unsafe fn copy_input(data: *const u8, size: usize) -> Option<Vec<u8>> {
+    if data.is_null() || size == 0 {
+        return None;
+    }
+    Some(std::slice::from_raw_parts(data, size).to_vec())
+}
 
 #[no_mangle]
-unsafe extern "C" fn auto_refresh_bntx(hash: u64, replace: *mut u8, size: usize) -> bool {
-    let slice = std::slice::from_raw_parts_mut(replace, size);
-    crate::bntx::handle_file_replace(Hash40::from(hash), slice)
+pub extern "C" fn auto_refresh_api_version() -> u32 {
+    AUTO_REFRESH_API_VERSION
 }
 
+/// Replaces the loaded textures of the BNTX `hash` with the ones in `replace`.
 #[no_mangle]
-unsafe extern "C" fn auto_refresh_file(hash: u64, replace: *mut u8, size: usize) -> bool {
-    let is_loaded = arcropolis_api::is_file_loaded(hash);
-    if is_loaded {
-        let fs = crate::resource::filesystem_info();
-        let loaded_arc = &fs.path_info.arc;
-
-        let file_info = loaded_arc
-            .get_file_info_from_hash(Hash40::from(hash))
-            .unwrap();
-        let loaded_data = &fs.get_loaded_datas()[file_info.file_info_indice_index.0 as usize];
-
-        if !loaded_data.data.is_null() {
-            let decompressed_size = loaded_arc
-                .get_file_data(file_info, Region::UsEnglish)
-                .decomp_size;
-            let slice = std::slice::from_raw_parts_mut(
-                loaded_data.data as *mut u8,
-                decompressed_size as usize,
-            );
-            let replace_slice = std::slice::from_raw_parts_mut(replace, size);
-            slice.copy_from_slice(replace_slice);
-
-            return true;
+pub unsafe extern "C" fn auto_refresh_bntx(
+    hash: u64,
+    replace: *const u8,
+    size: usize,
+) -> AutoRefreshStatus {
+    let data = match copy_input(replace, size) {
+        Some(data) => data,
+        None => return AutoRefreshStatus::InvalidArgument,
+    };
+    if !offsets::is_supported() || !bntx::is_enabled() {
+        return AutoRefreshStatus::Unsupported;
+    }
+    let path = format!("{:#x}", hash);
+    let job_path = path.clone();
+    let replaced =
+        match apply(move || bntx::handle_file_replace(&job_path, Hash40::from(hash), &data)) {
+            Ok(replaced) => replaced,
+            Err(status) => return status,
+        };
+    let (result, status) = match &replaced {
+        Ok(report) if report.rejected.is_empty() => {
+            (FileResult::refreshed(path), AutoRefreshStatus::Ok)
         }
-        return false;
+        Ok(report) if report.refreshed.is_empty() => (
+            FileResult::skipped(path, report.describe_rejected()),
+            AutoRefreshStatus::Failed,
+        ),
+        Ok(report) => (
+            FileResult::partial(path, report.describe_rejected()),
+            AutoRefreshStatus::Partial,
+        ),
+        Err(err) => (FileResult::skipped(path, err.to_string()), err.into()),
+    };
+    events::notify(hash, &result);
+    status
+}
+
+/// Overwrites the loaded file `hash` with `replace`, which has to match its size.
+#[no_mangle]
+pub unsafe extern "C" fn auto_refresh_file(
+    hash: u64,
+    replace: *const u8,
+    size: usize,
+) -> AutoRefreshStatus {
+    let data = match copy_input(replace, size) {
+        Some(data) => data,
+        None => return AutoRefreshStatus::InvalidArgument,
+    };
+    if !offsets::is_supported() {
+        return AutoRefreshStatus::Unsupported;
+    }
+    let path = format!("{:#x}", hash);
+    let job_path = path.clone();
+    let overwritten =
+        match apply(move || crate::overwrite_loaded_file(&job_path, Hash40::from(hash), &data)) {
+            Ok(overwritten) => overwritten,
+            Err(status) => return status,
+        };
+    let (result, status) = match &overwritten {
+        Ok(()) => (FileResult::refreshed(path), AutoRefreshStatus::Ok),
+        Err(err) => (FileResult::skipped(path, err.to_string()), err.into()),
+    };
+    events::notify(hash, &result);
+    status
+}
+
+/// Fills `info` with what auto-refresh knows about `hash`.
+#[no_mangle]
+pub unsafe extern "C" fn auto_refresh_query(
+    hash: u64,
+    info: *mut AutoRefreshFileInfo,
+) -> AutoRefreshStatus {
+    if info.is_null() {
+        return AutoRefreshStatus::InvalidArgument;
+    }
+    if !offsets::is_supported() {
+        return AutoRefreshStatus::Unsupported;
+    }
+    let loaded =
+        match apply(move || crate::loaded_buffer(Hash40::from(hash)).map(|buffer| buffer.len())) {
+            Ok(loaded) => loaded,
+            Err(status) => return status,
+        };
+    let loaded_size = match loaded {
+        Ok(size) => Some(size),
+        Err(RefreshError::NotLoaded) => None,
+        Err(err) => return (&err).into(),
+    };
+    let in_mods = index::FILES_INFO
+        .read()
+        .ok()
+        .map(|files| files.contains_hash(hash));
+    match (
+        in_mods,
+        pending::is_waiting(hash),
+        snapshot::has_snapshot(hash),
+    ) {
+        (Some(in_mods), Some(pending), Some(snapshotted)) => {
+            info.write(AutoRefreshFileInfo {
+                loaded: loaded_size.is_some(),
+                loaded_size: loaded_size.unwrap_or(0) as u64,
+                in_mods,
+                pending,
+                snapshotted,
+            });
+            AutoRefreshStatus::Ok
+        }
+        _ => AutoRefreshStatus::Internal,
+    }
+}
+
+/// Rebuilds the index of the mod folders. Blocks while the SD card is scanned.
+#[no_mangle]
+pub extern "C" fn auto_refresh_rescan() -> AutoRefreshStatus {
+    index::rescan();
+    AutoRefreshStatus::Ok
+}
+
+/// Calls `callback` after every refresh, on a thread of its own.
+#[no_mangle]
+pub extern "C" fn auto_refresh_register_callback(
+    callback: Option<AutoRefreshCallback>,
+    user_data: *mut c_void,
+) -> AutoRefreshStatus {
+    match callback {
+        Some(callback) => match events::register(callback, user_data) {
+            Some(true) => AutoRefreshStatus::Ok,
+            Some(false) => AutoRefreshStatus::AlreadyRegistered,
+            None => AutoRefreshStatus::Internal,
+        },
+        None => AutoRefreshStatus::InvalidArgument,
+    }
+}
+
+/// Takes the same `callback` and `user_data` that were registered.
+#[no_mangle]
+pub extern "C" fn auto_refresh_unregister_callback(
+    callback: Option<AutoRefreshCallback>,
+    user_data: *mut c_void,
+) -> AutoRefreshStatus {
+    match callback {
+        Some(callback) => match events::unregister(callback, user_data) {
+            Some(true) => AutoRefreshStatus::Ok,
+            Some(false) => AutoRefreshStatus::NotRegistered,
+            None => AutoRefreshStatus::Internal,
+        },
+        None => AutoRefreshStatus::InvalidArgument,
     }
-    return false;
 }
@@ -0,0 +1,221 @@
+use auto_refresh_common::arc_path;
+use auto_refresh_common::poll::Stamp;
+use auto_refresh_common::protocol::RootConflict;
+use once_cell::sync::Lazy;
+use smash_arc::Hash40;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+const MODS_DIR: &str = "sd:/ultimate/mods/";
+
+pub static FILES_INFO: Lazy<RwLock<FileIndex>> = Lazy::new(|| RwLock::new(FileIndex::default()));
+
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    /// Mod root providing the file
+    pub root: PathBuf,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub hash: Hash40,
+}
+
+impl IndexEntry {
+    pub fn physical_path(&self, arc_path: &str) -> PathBuf {
+        self.root.join(arc_path::to_sd_path(arc_path))
+    }
+
+    pub fn stamp(&self) -> Stamp {
+        Stamp {
+            size: self.size,
+            modified: self.modified,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub arc_path: String,
+    pub used_root: PathBuf,
+    pub ignored_root: PathBuf,
+}
+
+#[derive(Debug, Default)]
+pub struct FileIndex {
+    entries: HashMap<String, IndexEntry>,
+    conflicts: Vec<Conflict>,
+}
+
+impl FileIndex {
+    pub fn scan() -> Self {
+        let mut index = Self::default();
+        for root in mod_roots() {
+            index.scan_path(&root, &root);
+        }
+        index
+    }
+
+    fn scan_path(&mut self, root: &Path, path: &Path) {
+        match std::fs::read_dir(&path) {
+            Ok(entries) => {
+                for entry in entries.flatten() {
+                    let real_path = format!("{}", entry.path().display());
+                    let path = Path::new(&real_path);
+                    if path.is_dir() {
+                        self.scan_path(root, &path);
+                    } else {
+                        let sd_path = &real_path[format!("{}/", root.display()).len()..];
+                        self.insert(root, &arc_path::from_sd_path(sd_path), path);
+                    }
+                }
+            }
+            Err(err) => log!(Error, Refresh, "Error: {:?}", err),
+        }
+    }
+
+    fn insert(&mut self, root: &Path, arc_path: &str, physical_path: &Path) {
+        if let Some(existing) = self.entries.get(arc_path) {
+            log!(
+                Warn,
+                Refresh,
+                "Conflict: {} is provided by both {} and {}, using {}.",
+                arc_path,
+                existing.root.display(),
+                root.display(),
+                existing.root.display()
+            );
+            self.conflicts.push(Conflict {
+                arc_path: arc_path.to_string(),
+                used_root: existing.root.clone(),
+                ignored_root: root.to_path_buf(),
+            });
+            return;
+        }
+
+        let metadata = std::fs::metadata(physical_path).ok();
+        self.entries.insert(
+            arc_path.to_string(),
+            IndexEntry {
+                root: root.to_path_buf(),
+                size: metadata.as_ref().map(|meta| meta.len()).unwrap_or(0),
+                modified: metadata.and_then(|meta| meta.modified().ok()),
+                hash: smash_arc::hash40(arc_path),
+            },
+        );
+    }
+
+    pub fn get(&self, arc_path: &str) -> Option<&IndexEntry> {
+        self.entries.get(arc_path)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &IndexEntry)> {
+        self.entries.iter()
+    }
+
+    /// Records the size and modification time a file was refreshed with.
+    pub fn update_stamp(&mut self, arc_path: &str, stamp: Stamp) {
+        if let Some(entry) = self.entries.get_mut(arc_path) {
+            entry.size = stamp.size;
+            entry.modified = stamp.modified;
+        }
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.entries.keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn contains_hash(&self, hash: u64) -> bool {
+        self.entries
+            .values()
+            .any(|entry| entry.hash.as_u64() == hash)
+    }
+
+    /// Maps the hash of every indexed path back to the path.
+    pub fn paths_by_hash(&self) -> HashMap<u64, String> {
+        self.entries
+            .iter()
+            .map(|(arc_path, entry)| (entry.hash.as_u64(), arc_path.clone()))
+            .collect()
+    }
+}
+
+/// Every enabled ARCropolis mod folder, sorted by name. Folders starting with a `.` are disabled.
+pub fn mod_roots() -> Vec<PathBuf> {
+    let mut roots = vec![];
+    match std::fs::read_dir(MODS_DIR) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let is_disabled = entry.file_name().to_string_lossy().starts_with('.');
+                if path.is_dir() && !is_disabled {
+                    roots.push(path);
+                }
+            }
+        }
+        Err(err) => log!(Error, Refresh, "Error: {:?}", err),
+    }
+    roots.sort();
+    roots
+}
+
+/// Rebuilds the index from the SD card, returning the number of indexed files and the files
+/// provided by more than one mod folder.
+pub fn rescan() -> (usize, Vec<RootConflict>) {
+    let index = FileIndex::scan();
+    let file_count = index.len();
+    let conflicts: Vec<RootConflict> = index
+        .conflicts()
+        .iter()
+        .map(|conflict| RootConflict {
+            path: conflict.arc_path.clone(),
+            used_root: format!("{}", conflict.used_root.display()),
+            ignored_root: format!("{}", conflict.ignored_root.display()),
+        })
+        .collect();
+    *FILES_INFO.write().unwrap() = index;
+    log!(
+        Info,
+        Refresh,
+        "Indexed {} files ({} conflicts).",
+        file_count,
+        conflicts.len()
+    );
+    (file_count, conflicts)
+}
+
+/// Size and modification time of the file on SD, if it can be read.
+pub fn current_stamp(physical_path: &Path) -> Option<Stamp> {
+    let metadata = std::fs::metadata(physical_path).ok()?;
+    Some(Stamp {
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+    })
+}
+
+/// Every indexed ARC path, taken from the index as it is right now.
+pub fn indexed_paths() -> Vec<String> {
+    FILES_INFO.read().unwrap().paths()
+}
+
+/// Finds the file on SD backing an ARC path. Falls back to probing every mod root
+/// so files uploaded after the last scan can still be refreshed.
+pub fn resolve_path(arc_path: &str) -> Option<PathBuf> {
+    if let Some(entry) = FILES_INFO.read().unwrap().get(arc_path) {
+        return Some(entry.physical_path(arc_path));
+    }
+    mod_roots()
+        .into_iter()
+        .map(|root| root.join(arc_path::to_sd_path(arc_path)))
+        .find(|physical_path| physical_path.is_file())
+}
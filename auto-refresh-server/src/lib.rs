@@ -1,140 +1,542 @@
 #![feature(proc_macro_hygiene)]
 
-use once_cell::sync::Lazy;
+use auto_refresh_common::arc_path;
+use auto_refresh_common::backoff::{
+    Backoff, ACCEPT_BACKOFF, MAX_ACCEPT_BACKOFF, MAX_BIND_BACKOFF, REBIND_AFTER_FAILURES,
+};
+use auto_refresh_common::log::Level;
+use auto_refresh_common::protocol::{self, Command, FileResult, Request, ServerHello, Summary};
 use skyline::hooks::InlineCtx;
 use skyline::{hook, install_hook};
 use smash_arc::*;
 use std::collections::HashMap;
-use std::io::{Cursor, Read, Write};
+use std::io::{Cursor, ErrorKind, Read, Write};
 use std::net::TcpListener;
 use std::net::TcpStream;
 use std::path::Path;
-use std::sync::Mutex;
+use std::fmt;
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
+#[macro_use]
+mod log;
 mod bntx;
+mod config;
+mod discovery;
+mod events;
 mod ffi;
+mod index;
 mod offsets;
+mod overlay;
+mod pending;
+mod poll;
+mod queue;
 mod resource;
+mod snapshot;
+mod stream;
+mod trace;
+mod version;
 
 const BIND_ADDR: &str = "0.0.0.0:7878";
-const SCAN_DIR: &str = "sd:/ultimate/mods/Auto-Refresh/";
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Room for the command lines of a request, on top of an `OVERLAY` payload
+const MAX_REQUEST_HEADER: usize = 0x10000;
 
-static mut FILES_INFO: Lazy<Vec<String>> = Lazy::new(|| vec![]);
+/// Why a loaded buffer couldn't be overwritten.
+#[derive(Debug)]
+pub enum RefreshError {
+    /// The game doesn't have the file in memory
+    NotLoaded,
+    /// The hash isn't in the ARC
+    NotFound,
+    Failed(String),
+}
+
+impl From<String> for RefreshError {
+    fn from(reason: String) -> Self {
+        RefreshError::Failed(reason)
+    }
+}
+
+impl fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RefreshError::NotLoaded => write!(f, "not loaded"),
+            RefreshError::NotFound => write!(f, "not found in the arc"),
+            RefreshError::Failed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// A BNTX pushed before the game loads it is refreshed by the load hook when it comes in,
+/// which needs the hook to be installed.
+fn defer_or_skip(path: &str, file_hash: Hash40, data: Vec<u8>) -> FileResult {
+    if !bntx::is_enabled() {
+        return FileResult::skipped(
+            path,
+            "not loaded, and the load hook is unavailable for this game version",
+        );
+    }
+    pending::defer(file_hash.as_u64(), path, data)
+}
 
-pub fn refresh_file(path: &String) {
-    unsafe {
-        let file_hash = smash_arc::hash40(&path.to_owned());
-        if path.ends_with("bntx") {
-            if let Ok(data) = std::fs::read(Path::new(SCAN_DIR).join(path)) {
-                bntx::handle_file_replace(file_hash, &data);
+pub fn refresh_file(path: &String) -> FileResult {
+    let path = match arc_path::normalize(path) {
+        Ok(path) => path,
+        Err(err) => {
+            log!(Warn, Refresh, "Rejected {:?}: {}", path, err);
+            return FileResult::skipped(path.as_str(), err.to_string());
+        }
+    };
+    let file_hash = smash_arc::hash40(&path);
+    // The SD card has the newer file now, the overlay would serve the old one on the next load
+    overlay::clear(file_hash.as_u64());
+    let result = refresh_normalized(path, file_hash);
+    events::notify(file_hash.as_u64(), &result);
+    result
+}
+
+fn refresh_normalized(path: String, file_hash: Hash40) -> FileResult {
+    let physical_path = match index::resolve_path(&path) {
+        Some(physical_path) => physical_path,
+        None => {
+            log!(Warn, Refresh, "{} was not found in any mod folder.", path);
+            return FileResult::skipped(path.as_str(), "not found in any mod folder");
+        }
+    };
+    // So the SD polling doesn't refresh what a client just pushed a second time
+    if let Some(stamp) = index::current_stamp(&physical_path) {
+        index::FILES_INFO.write().unwrap().update_stamp(&path, stamp);
+    }
+    // Stream files can be whole videos, only their size matters here
+    if arc_path::is_stream(&path) {
+        return match std::fs::metadata(&physical_path) {
+            Ok(metadata) => stream::refresh(&path, file_hash, metadata.len()),
+            Err(err) => {
+                log!(Error, Refresh, "Error: {:?}", err);
+                FileResult::skipped(path.as_str(), format!("{:?}", err))
             }
-        } else {
-            let is_loaded = arcropolis_api::is_file_loaded(file_hash.as_u64());
-            println!("[auto-refresh] Updating file contents...");
-            if is_loaded {
-                let fs = resource::filesystem_info();
-                let loaded_arc = &fs.path_info.arc;
-
-                let file_info = loaded_arc.get_file_info_from_hash(file_hash).unwrap();
-                let loaded_data =
-                    &fs.get_loaded_datas()[file_info.file_info_indice_index.0 as usize];
-
-                if !loaded_data.data.is_null() {
-                    let decompressed_size = loaded_arc
-                        .get_file_data(file_info, Region::UsEnglish)
-                        .decomp_size;
-                    let slice = std::slice::from_raw_parts_mut(
-                        loaded_data.data as *mut u8,
-                        decompressed_size as usize,
-                    );
-                    println!("[auto-refresh] Overwriting buffer...");
-                    match std::fs::read(Path::new(SCAN_DIR).join(path)) {
-                        Ok(data) => slice.copy_from_slice(&data),
-                        Err(err) => println!("[auto-refresh] Error: {:?}", err),
-                    }
-                }
+        };
+    }
+    let data = match std::fs::read(&physical_path) {
+        Ok(data) => data,
+        Err(err) => {
+            log!(Error, Refresh, "Error: {:?}", err);
+            return FileResult::skipped(path.as_str(), format!("{:?}", err));
+        }
+    };
+    let data = Arc::new(data);
+    let job_data = data.clone();
+    let job_path = path.clone();
+    if path.ends_with("bntx") {
+        let replaced = queue::try_apply(move || {
+            bntx::handle_file_replace(&job_path, file_hash, &job_data)
+        });
+        return match replaced {
+            Ok(report) => report.to_result(&path),
+            Err(RefreshError::NotLoaded) => defer_or_skip(&path, file_hash, data.to_vec()),
+            Err(err) => FileResult::skipped(path.as_str(), err.to_string()),
+        };
+    }
+
+    match queue::try_apply(move || overwrite_loaded_file(&job_path, file_hash, &job_data)) {
+        Ok(()) => FileResult::refreshed(path.as_str()),
+        // Other files don't reliably go through the load hook, nothing is kept for them
+        Err(RefreshError::NotLoaded) => FileResult::skipped(
+            path.as_str(),
+            "not loaded, the game reads the new file from the SD card when it loads it",
+        ),
+        Err(err) => FileResult::skipped(path.as_str(), err.to_string()),
+    }
+}
+
+/// The buffer the game loaded `file_hash` into. Only call this from a `queue` job.
+pub fn loaded_buffer(file_hash: Hash40) -> Result<&'static mut [u8], RefreshError> {
+    if !offsets::is_supported() {
+        return Err("unsupported game version".to_string().into());
+    }
+
+    if !arcropolis_api::is_file_loaded(file_hash.as_u64()) {
+        return Err(RefreshError::NotLoaded);
+    }
+
+    let fs = resource::filesystem_info();
+    let loaded_arc = &fs.path_info.arc;
+
+    let file_info = match loaded_arc.get_file_info_from_hash(file_hash) {
+        Ok(file_info) => file_info,
+        Err(_) => return Err(RefreshError::NotFound),
+    };
+    let loaded_data = match fs
+        .get_loaded_datas()
+        .get(file_info.file_info_indice_index.0 as usize)
+    {
+        Some(loaded_data) => loaded_data,
+        None => return Err(RefreshError::NotLoaded),
+    };
+
+    if loaded_data.data.is_null() {
+        return Err(RefreshError::NotLoaded);
+    }
+
+    let decompressed_size = loaded_arc
+        .get_file_data(file_info, resource::region())
+        .decomp_size;
+    Ok(unsafe {
+        std::slice::from_raw_parts_mut(loaded_data.data as *mut u8, decompressed_size as usize)
+    })
+}
+
+/// Copies `data` over the loaded buffer of `file_hash`, snapshotting the original contents
+/// the first time. Only call this from a `queue` job.
+pub fn overwrite_loaded_file(
+    path: &str,
+    file_hash: Hash40,
+    data: &[u8],
+) -> Result<(), RefreshError> {
+    log!(Debug, Refresh, "Updating file contents...");
+    let buffer = loaded_buffer(file_hash)?;
+    if data.len() != buffer.len() {
+        return Err(format!(
+            "size {:#x} does not match loaded size {:#x}",
+            data.len(),
+            buffer.len()
+        )
+        .into());
+    }
+    snapshot::record(file_hash.as_u64(), path, buffer);
+    log!(Debug, Refresh, "Overwriting buffer...");
+    buffer.copy_from_slice(data);
+    Ok(())
+}
+
+pub fn refresh_files(paths: &[String]) -> Summary {
+    Summary {
+        results: paths.iter().map(refresh_file).collect(),
+    }
+}
+
+/// Ends the reply with the pending cursor from before the refresh when files were deferred, so
+/// the client follows only what resolves from then on.
+fn refresh_response(paths: &[String]) -> String {
+    let cursor = pending::cursor();
+    let summary = refresh_files(paths);
+    let mut response = summary.to_response();
+    if summary.pending_count() > 0 {
+        response.push_str(&protocol::cursor_line(cursor));
+        response.push('\n');
+    }
+    response
+}
+
+/// `request` is the raw request, for the commands carrying bytes.
+pub fn handle_command(command: Command, request: &[u8]) -> Vec<u8> {
+    let response = match command {
+        Command::Refresh(paths) => refresh_response(&paths),
+        Command::RefreshAll => refresh_response(&index::indexed_paths()),
+        Command::Rescan => {
+            let (file_count, conflicts) = index::rescan();
+            let mut response = format!(
+                "Rescanned! {} files, {} conflicts\n",
+                file_count,
+                conflicts.len()
+            );
+            for conflict in &conflicts {
+                response.push_str(&conflict.to_line());
+                response.push('\n');
             }
+            response
         }
+        Command::Status => status(),
+        Command::Pending(cursor) => pending::report(cursor),
+        Command::Restore(paths) => snapshot::restore(&paths).to_response(),
+        Command::RestoreAll => snapshot::restore_all().to_response(),
+        Command::Dump(path) => return dump_file(&path),
+        Command::TraceStart => trace::start(),
+        Command::TraceStop => auto_refresh_common::trace::to_response(&trace::stop()),
+        // Streamed by `handle_buffer` instead
+        Command::LogSubscribe(_) => String::new(),
+        Command::Overlay { path, .. } => format!("{}\n", overlay::push(&path, request).to_line()),
+        Command::Persist(paths) => overlay::persist(&paths).to_response(),
+        Command::Ping => format!("{}\n", protocol::PONG),
+    };
+    response.into_bytes()
+}
+
+/// The `DUMP` response for `path`: a header line and the loaded bytes.
+fn dump_file(path: &str) -> Vec<u8> {
+    let path = match arc_path::normalize(path) {
+        Ok(path) => path,
+        Err(err) => return skipped_response(path, &err.to_string()),
+    };
+    if arc_path::is_stream(&path) {
+        return skipped_response(&path, "stream files aren't kept in memory");
+    }
+    let file_hash = smash_arc::hash40(&path);
+    match queue::try_apply(move || loaded_buffer(file_hash).map(|buffer| buffer.to_vec())) {
+        Ok(data) => {
+            log!(Info, Refresh, "Dumping {} ({:#x} bytes).", path, data.len());
+            let mut response = format!("{}\n", protocol::dump_line(&path, data.len())).into_bytes();
+            response.extend_from_slice(&data);
+            response
+        }
+        Err(err) => skipped_response(&path, &err.to_string()),
     }
 }
 
-pub fn refresh_files() {
-    unsafe {
-        for file_path in FILES_INFO.iter() {
-            refresh_file(&file_path);
+fn skipped_response(path: &str, reason: &str) -> Vec<u8> {
+    format!("{}\n", FileResult::skipped(path, reason).to_line()).into_bytes()
+}
+
+fn status() -> String {
+    let mut response = format!(
+        "Game version {}: {}\n",
+        version::game_version(),
+        offsets::support()
+    );
+    response.push_str(&format!(
+        "Indexed files: {}\n",
+        index::FILES_INFO.read().unwrap().len()
+    ));
+
+    let (pending_count, pending_used, pending_budget) = pending::stats();
+    response.push_str(&format!(
+        "Pending refreshes: {} ({:#x} of {:#x} bytes)\n",
+        pending_count, pending_used, pending_budget
+    ));
+
+    let (snapshot_count, snapshot_used, snapshot_budget) = snapshot::stats();
+    response.push_str(&format!(
+        "Snapshots: {} ({:#x} of {:#x} bytes)\n",
+        snapshot_count, snapshot_used, snapshot_budget
+    ));
+
+    let (log_records, log_subscribers) = log::stats();
+    response.push_str(&format!(
+        "Log: {} records, {} subscribers\n",
+        log_records, log_subscribers
+    ));
+    let (overlay_count, overlay_persisted, overlay_used, overlay_budget) = overlay::stats();
+    response.push_str(&format!(
+        "Overlay: {} files, {} persisted ({:#x} of {:#x} bytes)\n",
+        overlay_count, overlay_persisted, overlay_used, overlay_budget
+    ));
+    response.push_str(&format!("Refresh callbacks: {}\n", events::count()));
+    response.push_str(&format!("SD polling: {}\n", poll::describe()));
+    response.push_str(&format!("Discovery: {}\n", discovery::describe()));
+    response.push_str(&format!(
+        "Load trace: {}\n",
+        if trace::is_tracing() { "recording" } else { "stopped" }
+    ));
+
+    let tracked = bntx::tracked_files();
+    response.push_str(&format!(
+        "Bntx refresh: {}, tracking {} buffers\n",
+        if bntx::is_enabled() { "enabled" } else { "disabled" },
+        tracked.len()
+    ));
+    for file in tracked {
+        response.push_str(&format!(
+            "  {:#x} at {:#x} ({:#x} bytes)\n",
+            file.hash, file.data_ptr, file.size
+        ));
+    }
+    response
+}
+
+/// The largest request worth reading: an `OVERLAY` that fills the whole overlay budget.
+fn max_request_size() -> usize {
+    config::CONFIG.overlay_budget.saturating_add(MAX_REQUEST_HEADER)
+}
+
+/// Reads a request until it is complete, as framed by `protocol::request_len`, or the client
+/// shuts down its write half. Anything else is served with whatever arrived before the timeout.
+fn read_request(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let max_size = max_request_size();
+    let mut buffer = vec![];
+    let mut chunk = [0; 1024];
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok(buffer),
+            Ok(size) if buffer.len() + size > max_size => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("request is larger than {:#x} bytes", max_size),
+                ))
+            }
+            Ok(size) => {
+                buffer.extend_from_slice(&chunk[..size]);
+                if let Some(len) = protocol::request_len(&buffer) {
+                    buffer.truncate(len);
+                    return Ok(buffer);
+                }
+            }
+            Err(err)
+                if !buffer.is_empty()
+                    && (err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut) =>
+            {
+                return Ok(buffer)
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
         }
     }
 }
 
 pub fn handle_buffer(mut stream: TcpStream) {
-    let mut buffer = [0; 1024];
-    match stream.read(&mut buffer) {
-        Ok(size) => {
-            let data = String::from_utf8_lossy(&buffer[0..size]).into_owned();
-            let data = data.trim();
-            let mut response: String = "Refreshed!".to_string();
-            println!("{}", data);
-            let lines: Vec<&str> = data.split("\n").collect();
-            if lines.len() >= 1 {
-                for line in lines.iter() {
-                    refresh_file(&line.to_string());
+    if let Err(err) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+        log!(Warn, Network, "Failed to set read timeout: {:?}", err);
+    }
+
+    match read_request(&mut stream) {
+        Ok(buffer) => {
+            // Only the text lines, an OVERLAY's payload stays in `buffer`
+            let header = String::from_utf8_lossy(protocol::request_header(&buffer));
+            let request = Request::parse(&header);
+            log!(
+                Debug,
+                Network,
+                "{} request ({:#x} bytes)",
+                request.command.name(),
+                buffer.len()
+            );
+
+            let response = if !config::CONFIG.check_token(request.token()) {
+                match stream.peer_addr() {
+                    Ok(addr) => log!(Warn, Network, "Rejected {}: invalid token.", addr),
+                    Err(_) => log!(Warn, Network, "Rejected connection: invalid token."),
                 }
+                format!("{}\n", protocol::denied_line("invalid token")).into_bytes()
             } else {
-                refresh_files();
-            }
+                let mut response = vec![];
+                if request.hello.is_some() {
+                    let hello = ServerHello::new(
+                        env!("CARGO_PKG_VERSION"),
+                        version::game_version(),
+                        offsets::support().clone(),
+                    );
+                    response.extend_from_slice(hello.to_line().as_bytes());
+                    response.push(b'\n');
+                }
+                if let Command::LogSubscribe(level) = request.command {
+                    stream_log(stream, &response, level);
+                    return;
+                }
+                response.extend_from_slice(&handle_command(request.command, &buffer));
+                response
+            };
 
-            match stream.write(response.as_bytes()) {
+            match stream.write_all(&response) {
                 Ok(_ok) => {}
                 Err(err) => {
-                    println!("[auto-refresh] Stream Write Error: {:?}", err);
+                    log!(Error, Network, "Stream Write Error: {:?}", err);
                 }
             }
         }
         Err(err) => {
-            println!("[auto-refresh] Network Error: {:?}", err);
-            stream.flush().unwrap();
+            log!(Error, Network, "Network Error: {:?}", err);
         }
     }
 
-    stream.shutdown(std::net::Shutdown::Both);
-}
-
-pub fn scan_path_for_files(path: &Path) {
-    match std::fs::read_dir(&path) {
-        Ok(entries) => {
-            for entry in entries {
-                let entry = entry.unwrap();
-                let real_path = format!("{}", entry.path().display());
-                let path = Path::new(&real_path);
-                if path.is_dir() {
-                    scan_path_for_files(&path);
-                } else {
-                    let arc_path = &real_path[SCAN_DIR.len()..];
-                    unsafe {
-                        println!("{:?}", arc_path);
-                        FILES_INFO.push(arc_path.to_string());
-                    }
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+}
+
+/// Keeps the connection for a `LOG_SUBSCRIBE` request, after sending `hello`.
+fn stream_log(mut stream: TcpStream, hello: &[u8], level: Level) {
+    let streamed = stream
+        .write_all(hello)
+        .and_then(|_| log::stream_to(&mut stream, level));
+    if let Err(err) = streamed {
+        log!(Info, Network, "Log subscriber disconnected: {:?}", err);
+    }
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+}
+
+fn listen() {
+    let mut bind_backoff = Backoff::new(ACCEPT_BACKOFF, MAX_BIND_BACKOFF);
+    loop {
+        match TcpListener::bind(BIND_ADDR) {
+            Ok(listener) => {
+                bind_backoff.succeed();
+                accept_connections(&listener);
+                log!(
+                    Warn,
+                    Network,
+                    "Accepting connections keeps failing, binding {} again.",
+                    BIND_ADDR
+                );
+            }
+            Err(err) => {
+                let delay = bind_backoff.fail();
+                log!(
+                    Error,
+                    Network,
+                    "Failed to bind {}: {:?}, retrying in {}ms",
+                    BIND_ADDR,
+                    err,
+                    delay.as_millis()
+                );
+                thread::sleep(delay);
+            }
+        }
+    }
+}
+
+/// Serves connections until accepting fails `REBIND_AFTER_FAILURES` times in a row.
+fn accept_connections(listener: &TcpListener) {
+    let mut backoff = Backoff::new(ACCEPT_BACKOFF, MAX_ACCEPT_BACKOFF);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => {
+                backoff.succeed();
+                stream
+            }
+            Err(err) => {
+                log!(Error, Network, "Accept Error: {:?}", err);
+                let delay = backoff.fail();
+                if backoff.failures() >= REBIND_AFTER_FAILURES {
+                    return;
                 }
+                thread::sleep(delay);
+                continue;
+            }
+        };
+
+        match stream.peer_addr() {
+            Ok(addr) if !config::CONFIG.is_allowed(&addr.ip()) => {
+                log!(Warn, Network, "Rejected {}: not in the allowlist.", addr);
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                continue;
+            }
+            Ok(addr) => log!(Debug, Network, "Connection established with {}!", addr),
+            Err(err) if !config::CONFIG.allowlist.is_empty() => {
+                log!(Warn, Network, "Rejected connection with unknown address: {:?}", err);
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                continue;
             }
+            Err(_) => log!(Debug, Network, "Connection established!"),
+        }
+
+        let worker = thread::Builder::new()
+            .name("auto-refresh-connection".to_string())
+            .spawn(move || handle_buffer(stream));
+        if let Err(err) = worker {
+            log!(Error, Network, "Failed to spawn connection worker: {:?}", err);
         }
-        Err(err) => println!("[auto-refresh] Error: {:?}", err),
     }
 }
 
 #[skyline::main(name = "auto-refresh")]
 pub fn main() {
-    bntx::install();
+    log::init(&config::CONFIG);
+    offsets::log_report();
 
-    scan_path_for_files(Path::new(SCAN_DIR));
+    bntx::install();
 
-    thread::spawn(|| {
-        let listener = TcpListener::bind(BIND_ADDR).unwrap();
-        for stream in listener.incoming() {
-            let stream = stream.unwrap();
+    index::rescan();
+    poll::start();
+    discovery::start();
 
-            println!("Connection established!");
-            handle_buffer(stream);
-        }
-    });
+    thread::spawn(listen);
 }
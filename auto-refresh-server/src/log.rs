@@ -0,0 +1,179 @@
+use auto_refresh_common::log::{Category, Level, LogRecord, RingBuffer, RING_CAPACITY};
+use once_cell::sync::Lazy;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// `log!(Warn, Refresh, "{} was not found", path)`
+macro_rules! log {
+    ($level:ident, $category:ident, $($arg:tt)*) => {
+        $crate::log::write(
+            auto_refresh_common::log::Level::$level,
+            auto_refresh_common::log::Category::$category,
+            format!($($arg)*),
+        )
+    };
+}
+
+static START: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Milliseconds since the plugin started, which log records and resolved refreshes are
+/// stamped with.
+pub fn uptime_ms() -> u64 {
+    START.elapsed().as_millis() as u64
+}
+
+/// Index into `Level::ALL`. Stays at info until the config is read, and can't be read from
+/// the config directly since loading the config logs too.
+static MIN_LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+
+static RING: Lazy<Mutex<RingBuffer>> = Lazy::new(|| Mutex::new(RingBuffer::new(RING_CAPACITY)));
+
+/// Records queued for a live client before new ones are dropped, so one that stops reading
+/// doesn't make the server keep every record for it.
+const SUBSCRIBER_QUEUE: usize = 256;
+/// A live client that doesn't take a record for this long is disconnected.
+const SUBSCRIBER_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Subscriber {
+    /// Lowest level the client asked for
+    level: Level,
+    sender: SyncSender<LogRecord>,
+    /// Records dropped since the client last caught up
+    dropped: Arc<AtomicUsize>,
+}
+
+static SUBSCRIBERS: Lazy<Mutex<Vec<Subscriber>>> = Lazy::new(|| Mutex::new(vec![]));
+
+/// Records are written to the SD card from a thread of their own, so logging from the load
+/// hooks never waits on file IO.
+static FILE_WRITER: Lazy<Mutex<Option<Sender<LogRecord>>>> = Lazy::new(|| Mutex::new(None));
+
+fn min_level() -> Level {
+    Level::ALL[MIN_LEVEL.load(Ordering::SeqCst)]
+}
+
+pub fn write(level: Level, category: Category, message: String) {
+    if level < min_level() {
+        return;
+    }
+    println!("[auto-refresh] {}", message);
+
+    // The ring stays locked until every subscriber got the record, so one subscribing
+    // meanwhile sees it exactly once, either in its backlog or live.
+    // Already printed, the rest is skipped rather than panicking if a lock was poisoned
+    let mut ring = match RING.lock() {
+        Ok(ring) => ring,
+        Err(_) => return,
+    };
+    let record = ring.push(
+        uptime_ms(),
+        level,
+        category,
+        message,
+    );
+    if let Ok(writer) = FILE_WRITER.lock() {
+        if let Some(writer) = writer.as_ref() {
+            let _ = writer.send(record.clone());
+        }
+    }
+    if let Ok(mut subscribers) = SUBSCRIBERS.lock() {
+        subscribers.retain(|subscriber| {
+            if record.level < subscriber.level {
+                return true;
+            }
+            match subscriber.sender.try_send(record.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    subscriber.dropped.fetch_add(1, Ordering::SeqCst);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
+
+/// Applies the config's level and starts writing to its log file, beginning with whatever was
+/// logged before.
+pub fn init(config: &Config) {
+    MIN_LEVEL.store(config.log_level as usize, Ordering::SeqCst);
+
+    let path = match &config.log_file {
+        Some(path) => path,
+        None => return,
+    };
+    let mut file = match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            log!(Warn, Config, "Failed to open log file {}: {:?}", path, err);
+            return;
+        }
+    };
+
+    let (sender, receiver) = channel::<LogRecord>();
+    {
+        let ring = RING.lock().unwrap();
+        for record in ring.records(Level::Debug) {
+            let _ = writeln!(file, "{}", record);
+        }
+        *FILE_WRITER.lock().unwrap() = Some(sender);
+    }
+    thread::spawn(move || {
+        for record in receiver {
+            let _ = writeln!(file, "{}", record);
+        }
+    });
+}
+
+/// Sends the stored records at or above `level`, then every new one, until the client goes
+/// away or stops reading. Records the client is too slow for are dropped, with a note saying
+/// how many.
+pub fn stream_to(stream: &mut TcpStream, level: Level) -> std::io::Result<()> {
+    stream.set_write_timeout(Some(SUBSCRIBER_WRITE_TIMEOUT))?;
+    let (sender, receiver) = sync_channel(SUBSCRIBER_QUEUE);
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let backlog = {
+        let ring = RING.lock().unwrap();
+        SUBSCRIBERS.lock().unwrap().push(Subscriber {
+            level,
+            sender,
+            dropped: dropped.clone(),
+        });
+        ring.records(level)
+    };
+    for record in backlog {
+        writeln!(stream, "{}", record.to_line())?;
+    }
+    for record in receiver {
+        let missed = dropped.swap(0, Ordering::SeqCst);
+        if missed > 0 {
+            let note = LogRecord {
+                level: Level::Warn,
+                category: Category::Network,
+                message: format!(
+                    "{} records were dropped, they came in faster than they were read.",
+                    missed
+                ),
+                ..record.clone()
+            };
+            writeln!(stream, "{}", note.to_line())?;
+        }
+        writeln!(stream, "{}", record.to_line())?;
+    }
+    Ok(())
+}
+
+/// Number of stored records and of live subscribers.
+pub fn stats() -> (usize, usize) {
+    let ring = RING.lock().unwrap();
+    let subscribers = SUBSCRIBERS.lock().unwrap().len();
+    (ring.len(), subscribers)
+}
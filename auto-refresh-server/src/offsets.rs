@@ -1,43 +1,65 @@
+use auto_refresh_common::protocol::GameSupport;
+use auto_refresh_common::signature::{self, Candidate, ScanReport, Signature};
 use once_cell::sync::Lazy;
 use skyline::hooks::{getRegionAddress, Region};
 
-static OFFSETS: Lazy<Offsets> = Lazy::new(|| Offsets::new());
+use crate::version::{self, KnownVersion};
 
-static FILESYSTEM_INFO_ADRP_SEARCH_CODE: &[u8] = &[
-    0xf3, 0x03, 0x00, 0xaa, 0x1f, 0x01, 0x09, 0x6b, 0xe0, 0x04, 0x00, 0x54,
-];
-
-static RES_SERVICE_ADRP_SEARCH_CODE: &[u8] = &[
-    0x04, 0x01, 0x49, 0xfa, 0x21, 0x05, 0x00, 0x54, 0x5f, 0x00, 0x00, 0xf9, 0x7f, 0x00, 0x00, 0xf9,
-];
-
-fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
-    haystack
-        .windows(needle.len())
-        .position(|window| window == needle)
-}
+static OFFSETS: Lazy<Offsets> =
+    Lazy::new(|| Offsets::new(get_text(), version::known_version()));
 
-#[allow(clippy::inconsistent_digit_grouping)]
-fn offset_from_adrp(adrp_offset: usize) -> usize {
-    unsafe {
-        let adrp = *(offset_to_addr(adrp_offset) as *const u32);
-        let immhi = (adrp & 0b0000_0000_1111_1111_1111_1111_1110_0000) >> 3;
-        let immlo = (adrp & 0b0110_0000_0000_0000_0000_0000_0000_0000) >> 29;
-        let imm = ((immhi | immlo) << 12) as i32 as usize;
-        let base = adrp_offset & 0xFFFF_FFFF_FFFF_F000;
-        base + imm
-    }
-}
+pub const FILESYSTEM_INFO: &str = "filesystem_info";
+pub const RES_SERVICE: &str = "res_service";
+pub const LOAD_FILES_THREADED: &str = "load_files_threaded";
+pub const FREE_FILES_THREADED: &str = "free_files_threaded";
 
-#[allow(clippy::inconsistent_digit_grouping)]
-fn offset_from_ldr(ldr_offset: usize) -> usize {
-    unsafe {
-        let ldr = *(offset_to_addr(ldr_offset) as *const u32);
-        let size = (ldr & 0b1100_0000_0000_0000_0000_0000_0000_0000) >> 30;
-        let imm = (ldr & 0b0000_0000_0011_1111_1111_1100_0000_0000) >> 10;
-        (imm as usize) << size
-    }
-}
+static SIGNATURES: &[Signature] = &[
+    // Globals: the candidate points at an adrp followed by the ldr of the global's address
+    Signature {
+        name: FILESYSTEM_INFO,
+        candidates: &[Candidate {
+            pattern: "f3 03 00 aa 1f 01 09 6b e0 04 00 54",
+            delta: 12,
+        }],
+    },
+    Signature {
+        name: RES_SERVICE,
+        candidates: &[Candidate {
+            pattern: "04 01 49 fa 21 05 00 54 5f 00 00 f9 7f 00 00 f9",
+            delta: 16,
+        }],
+    },
+    // ldr x1, [x19, #0x8]; ldr x2, [x19, #0x10]; mov x0, x20
+    // The hook goes on the first load, where x19 holds the finished ThreadedFileLoad.
+    Signature {
+        name: LOAD_FILES_THREADED,
+        candidates: &[
+            Candidate {
+                pattern: "61 06 40 f9 62 0a 40 f9 e0 03 14 aa",
+                delta: 0,
+            },
+            Candidate {
+                pattern: "61 06 40 f9 62 0a 40 f9 e0 03 ?? aa",
+                delta: 0,
+            },
+        ],
+    },
+    // ldr w8, [x0]; mov w9, #0xffff; movk w9, #0xff, lsl #16; cmp w8, w9
+    // The hook goes on the first load, where x0 holds the ThreadedFileLoad being freed.
+    Signature {
+        name: FREE_FILES_THREADED,
+        candidates: &[
+            Candidate {
+                pattern: "08 00 40 b9 e9 ff 9f 52 e9 1f a0 72 1f 01 09 6b",
+                delta: 0,
+            },
+            Candidate {
+                pattern: "?? 00 40 b9 ?? ff 9f 52 ?? 1f a0 72",
+                delta: 0,
+            },
+        ],
+    },
+];
 
 pub fn offset_to_addr(offset: usize) -> *const () {
     unsafe { (getRegionAddress(Region::Text) as *const u8).add(offset) as _ }
@@ -51,43 +73,133 @@ fn get_text() -> &'static [u8] {
     }
 }
 
-struct Offsets {
-    pub filesystem_info: usize,
-    pub res_service: usize,
+pub struct Offsets {
+    pub filesystem_info: Option<usize>,
+    pub res_service: Option<usize>,
+    pub load_files_threaded: Option<usize>,
+    pub free_files_threaded: Option<usize>,
+    pub report: ScanReport,
+    pub support: GameSupport,
 }
 
 impl Offsets {
-    pub fn new() -> Self {
-        let text = get_text();
-
-        let filesystem_info = {
-            let adrp = find_subsequence(text, FILESYSTEM_INFO_ADRP_SEARCH_CODE)
-                .expect("Unable to find subsequence")
-                + 12;
-            let adrp_offset = offset_from_adrp(adrp);
-            let ldr_offset = offset_from_ldr(adrp + 4);
-            adrp_offset + ldr_offset
+    /// Scans `text`, which may be the running game or a dump of it, and cross-checks the
+    /// results against `known`, the offset table of the running version if there is one.
+    /// Never panics, missing symbols are left as `None` and described in `report`.
+    pub fn new(text: &[u8], known: Option<&KnownVersion>) -> Self {
+        let report = signature::scan(text, SIGNATURES);
+        let resolve_global = |name| {
+            report
+                .offset(name)
+                .and_then(|adrp| signature::resolve_adrp_ldr(text, adrp))
         };
-        let res_service = {
-            let adrp = find_subsequence(text, RES_SERVICE_ADRP_SEARCH_CODE)
-                .expect("Unable to find subsequence")
-                + 16;
-            let adrp_offset = offset_from_adrp(adrp);
-            let ldr_offset = offset_from_ldr(adrp + 4);
-            adrp_offset + ldr_offset
+
+        let mut mismatches = vec![];
+        // Missing, or not confirmed by both the scan and the offset table
+        let mut unchecked = vec![];
+        let mut pick = |name: &str, scanned: Option<usize>| -> Option<usize> {
+            match (scanned, known.and_then(|known| known.offset(name))) {
+                (Some(scanned), Some(expected)) if scanned != expected => {
+                    log!(
+                        Warn,
+                        Offsets,
+                        "Offset mismatch for {}: scanned {:#x}, expected {:#x}. Not using it.",
+                        name, scanned, expected
+                    );
+                    mismatches.push(name.to_string());
+                    None
+                }
+                (Some(scanned), Some(_)) => Some(scanned),
+                (scanned, expected) => {
+                    unchecked.push(name.to_string());
+                    scanned.or(expected)
+                }
+            }
+        };
+
+        let filesystem_info = pick(FILESYSTEM_INFO, resolve_global(FILESYSTEM_INFO));
+        let res_service = pick(RES_SERVICE, resolve_global(RES_SERVICE));
+        let load_files_threaded = pick(LOAD_FILES_THREADED, report.offset(LOAD_FILES_THREADED));
+        let free_files_threaded = pick(FREE_FILES_THREADED, report.offset(FREE_FILES_THREADED));
+
+        // Without the hooks only BNTX refreshes are off, without these nothing works
+        let missing: Vec<String> = [(FILESYSTEM_INFO, filesystem_info), (RES_SERVICE, res_service)]
+            .iter()
+            .filter(|(_, offset)| offset.is_none())
+            .map(|(name, _)| name.to_string())
+            .collect();
+
+        let support = if !mismatches.is_empty() {
+            GameSupport::Mismatch(mismatches)
+        } else if !missing.is_empty() {
+            GameSupport::Unsupported(missing)
+        } else if known.is_none() {
+            GameSupport::UnknownVersion
+        } else if !unchecked.is_empty() {
+            GameSupport::Partial(unchecked)
+        } else {
+            GameSupport::Verified
         };
 
         Self {
             filesystem_info,
             res_service,
+            load_files_threaded,
+            free_files_threaded,
+            report,
+            support,
         }
     }
 }
 
-pub fn filesystem_info() -> usize {
+/// Logs which pattern matched where, or which symbols are missing.
+pub fn log_report() {
+    log!(
+        Info,
+        Offsets,
+        "Game version {}: {}",
+        version::game_version(),
+        OFFSETS.support
+    );
+    for line in OFFSETS.report.to_string().lines() {
+        log!(Info, Offsets, "Offset scan: {}", line);
+    }
+    let missing = OFFSETS.report.missing();
+    if !missing.is_empty() {
+        log!(
+            Warn,
+            Offsets,
+            "Unknown game build, missing offsets: {}",
+            missing.join(", ")
+        );
+    }
+}
+
+pub fn report() -> &'static ScanReport {
+    &OFFSETS.report
+}
+
+pub fn support() -> &'static GameSupport {
+    &OFFSETS.support
+}
+
+/// Whether the offsets needed to touch loaded files were found.
+pub fn is_supported() -> bool {
+    OFFSETS.filesystem_info.is_some() && OFFSETS.res_service.is_some()
+}
+
+pub fn filesystem_info() -> Option<usize> {
     OFFSETS.filesystem_info
 }
 
-pub fn res_service() -> usize {
+pub fn res_service() -> Option<usize> {
     OFFSETS.res_service
 }
+
+pub fn load_files_threaded() -> Option<usize> {
+    OFFSETS.load_files_threaded
+}
+
+pub fn free_files_threaded() -> Option<usize> {
+    OFFSETS.free_files_threaded
+}
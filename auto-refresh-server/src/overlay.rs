@@ -0,0 +1,207 @@
+use auto_refresh_common::arc_path;
+use auto_refresh_common::overlay::{Inserted, OverlayStore};
+use auto_refresh_common::protocol::{self, FileResult, Summary};
+use once_cell::sync::Lazy;
+use smash_arc::{ArcLookup, Hash40};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::{bntx, config, events, index, offsets, overwrite_loaded_file, queue, resource};
+
+/// Overlaid files no mod folder provides are persisted into this one.
+const PERSIST_ROOT: &str = "sd:/ultimate/mods/auto-refresh-overlay";
+
+static OVERLAY: Lazy<Mutex<OverlayStore>> =
+    Lazy::new(|| Mutex::new(OverlayStore::new(config::CONFIG.overlay_budget)));
+
+/// Size each hash's callback was registered with. ARCropolis hands the callback a buffer of
+/// that size, so a larger push registers the hash again.
+static REGISTERED: Lazy<Mutex<HashMap<u64, usize>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// ARCropolis file callback, called when the game loads an overlaid hash.
+extern "C" fn serve(hash: u64, out: *mut u8, length: usize, out_size: &mut usize) -> bool {
+    if out.is_null() {
+        return false;
+    }
+    let buffer = unsafe { std::slice::from_raw_parts_mut(out, length) };
+    if let Some(file) = OVERLAY.lock().unwrap().get(hash) {
+        if file.data.len() <= length {
+            buffer[..file.data.len()].copy_from_slice(&file.data);
+            *out_size = file.data.len();
+            log!(Info, Refresh, "Served {} from the overlay.", file.path);
+            return true;
+        }
+        log!(
+            Warn,
+            Refresh,
+            "{} is larger than the {:#x} bytes ARCropolis allotted, loading the original.",
+            file.path,
+            length
+        );
+    }
+    match arcropolis_api::load_original_file(hash, buffer) {
+        Some(size) => {
+            *out_size = size;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Decompressed size of the file in the ARC, 0 if it can't be looked up yet.
+fn original_size(hash: u64) -> usize {
+    if !offsets::is_supported() || !resource::initialized() {
+        return 0;
+    }
+    let arc = resource::arc();
+    arc.get_file_info_from_hash(Hash40::from(hash))
+        .map(|file_info| arc.get_file_data(file_info, resource::region()).decomp_size as usize)
+        .unwrap_or(0)
+}
+
+fn register(hash: u64, size: usize) {
+    let mut registered = REGISTERED.lock().unwrap();
+    if registered
+        .get(&hash)
+        .map_or(false, |registered| *registered >= size)
+    {
+        return;
+    }
+    // Leave room for the original, which is loaded if the overlay can't be used
+    let max_size = size.max(original_size(hash));
+    arcropolis_api::register_callback(hash, max_size, serve);
+    registered.insert(hash, max_size);
+}
+
+/// Keeps the file carried by an `OVERLAY` request. A loaded file is refreshed in place as well
+/// when it can be, everything else takes effect the next time the game loads it.
+pub fn push(path: &str, request: &[u8]) -> FileResult {
+    let overlay = match protocol::parse_overlay(request) {
+        Ok(overlay) => overlay,
+        Err(reason) => return FileResult::skipped(path, reason),
+    };
+    let path = match arc_path::normalize(&overlay.path) {
+        Ok(path) => path,
+        Err(err) => return FileResult::skipped(overlay.path.as_str(), err.to_string()),
+    };
+    if arc_path::is_stream(&path) {
+        return FileResult::skipped(path.as_str(), "stream files can't be overlaid");
+    }
+    let file_hash = smash_arc::hash40(&path);
+    let data = overlay.data.to_vec();
+
+    {
+        let mut overlay = OVERLAY.lock().unwrap();
+        if overlay.insert(file_hash.as_u64(), &path, data.clone()) == Inserted::OverBudget {
+            return FileResult::skipped(
+                path.as_str(),
+                format!(
+                    "the overlay budget of {:#x} bytes is used up",
+                    overlay.budget()
+                ),
+            );
+        }
+    }
+    log!(
+        Info,
+        Refresh,
+        "Overlaid {} ({:#x} bytes).",
+        path,
+        data.len()
+    );
+    register(file_hash.as_u64(), data.len());
+
+    let job_path = path.clone();
+    let applied = queue::try_apply(move || {
+        if job_path.ends_with("bntx") {
+            bntx::handle_file_replace(&job_path, file_hash, &data)
+                .map(|report| report.rejected.is_empty())
+        } else {
+            overwrite_loaded_file(&job_path, file_hash, &data).map(|_| true)
+        }
+    });
+    let result = match applied {
+        Ok(true) => FileResult::refreshed(path.as_str()),
+        _ => FileResult::pending(
+            path.as_str(),
+            "overlaid, used the next time the game loads it",
+        ),
+    };
+    events::notify(file_hash.as_u64(), &result);
+    result
+}
+
+/// Writes the overlaid bytes of `hash` to the mod folder providing it, or to `PERSIST_ROOT`.
+fn persist_hash(hash: u64) -> Option<FileResult> {
+    let file = OVERLAY.lock().unwrap().get(hash)?.clone();
+    if file.persisted {
+        log!(Info, Refresh, "{} is already persisted.", file.path);
+        return Some(FileResult::refreshed(file.path.as_str()));
+    }
+    let physical_path = index::resolve_path(&file.path)
+        .unwrap_or_else(|| PathBuf::from(PERSIST_ROOT).join(&file.path));
+    if let Some(parent) = physical_path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    match std::fs::write(&physical_path, &file.data) {
+        Ok(()) => {
+            log!(
+                Info,
+                Refresh,
+                "Persisted {} to {}.",
+                file.path,
+                physical_path.display()
+            );
+            OVERLAY.lock().unwrap().mark_persisted(hash);
+            Some(FileResult::refreshed(file.path.as_str()))
+        }
+        Err(err) => Some(FileResult::skipped(
+            file.path.as_str(),
+            format!("failed to write {}: {:?}", physical_path.display(), err),
+        )),
+    }
+}
+
+/// Persists the listed paths, or every overlaid file if none are listed.
+pub fn persist(paths: &[String]) -> Summary {
+    if paths.is_empty() {
+        let hashes = OVERLAY.lock().unwrap().hashes();
+        return Summary {
+            results: hashes.into_iter().filter_map(persist_hash).collect(),
+        };
+    }
+    Summary {
+        results: paths
+            .iter()
+            .map(|path| match arc_path::normalize(path) {
+                Ok(path) => persist_hash(smash_arc::hash40(&path).as_u64())
+                    .unwrap_or_else(|| FileResult::skipped(path.as_str(), "not overlaid")),
+                Err(err) => FileResult::skipped(path.as_str(), err.to_string()),
+            })
+            .collect(),
+    }
+}
+
+/// Drops the overlaid bytes of `hash` ahead of a regular refresh, which reads the SD card.
+pub fn clear(hash: u64) {
+    if let Some(file) = OVERLAY.lock().unwrap().remove(hash) {
+        log!(
+            Info,
+            Refresh,
+            "Dropped {} from the overlay, refreshing it from the SD card.",
+            file.path
+        );
+    }
+}
+
+/// Number of overlaid files, how many of them are persisted, the bytes they hold and the budget.
+pub fn stats() -> (usize, usize, usize, usize) {
+    let overlay = OVERLAY.lock().unwrap();
+    (
+        overlay.len(),
+        overlay.persisted_count(),
+        overlay.used(),
+        overlay.budget(),
+    )
+}
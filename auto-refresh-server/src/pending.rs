@@ -0,0 +1,83 @@
+use auto_refresh_common::pending::{Deferred, PendingTable};
+use auto_refresh_common::protocol::{self, FileResult, Summary};
+use once_cell::sync::Lazy;
+use smash_arc::Hash40;
+use std::sync::Mutex;
+
+use crate::{bntx, config, events, log};
+
+static PENDING: Lazy<Mutex<PendingTable>> =
+    Lazy::new(|| Mutex::new(PendingTable::new(config::CONFIG.pending_budget)));
+
+/// Keeps `data` until the game loads `path`, if it fits the budget.
+pub fn defer(hash: u64, path: &str, data: Vec<u8>) -> FileResult {
+    let mut pending = PENDING.lock().unwrap();
+    match pending.insert(hash, path.to_string(), data) {
+        Deferred::New | Deferred::Replaced => {
+            log!(
+                Info,
+                Refresh,
+                "{} is not loaded, refreshing it once the game loads it.",
+                path
+            );
+            FileResult::pending(path, "waiting for the game to load it")
+        }
+        Deferred::OverBudget => {
+            log!(
+                Warn,
+                Refresh,
+                "Pending budget of {:#x} bytes is used up, not waiting for {} to load.",
+                pending.budget(),
+                path
+            );
+            FileResult::skipped(path, "not loaded, and the pending budget is used up")
+        }
+    }
+}
+
+/// Called from the load hook once the BNTX tracker knows the freshly loaded file.
+pub fn on_load(hash: u64) {
+    // Resolving may take the snapshot and tracker locks, so the table is released first
+    let pending = match PENDING.lock().unwrap().take(hash) {
+        Some(pending) => pending,
+        None => return,
+    };
+    let result = match bntx::handle_file_replace(&pending.path, Hash40::from(hash), &pending.data) {
+        Ok(report) => report.to_result(&pending.path),
+        Err(err) => FileResult::skipped(pending.path.as_str(), err.to_string()),
+    };
+    log!(Info, Refresh, "{} loaded: {}", pending.path, result);
+    events::notify(hash, &result);
+    PENDING
+        .lock()
+        .unwrap()
+        .record_resolved(result, log::uptime_ms());
+}
+
+/// Cursor to read only the refreshes that resolve from now on.
+pub fn cursor() -> u64 {
+    PENDING.lock().unwrap().cursor()
+}
+
+/// `None` if the pending table can't be read.
+pub fn is_waiting(hash: u64) -> Option<bool> {
+    Some(PENDING.lock().ok()?.contains(hash))
+}
+
+/// Refreshes still waiting, followed by those resolved since `cursor`, then the cursor to read
+/// from next time.
+pub fn report(cursor: Option<u64>) -> String {
+    let mut pending = PENDING.lock().unwrap();
+    let mut results = pending.waiting();
+    results.append(&mut pending.resolved_since(cursor, log::uptime_ms()));
+    let mut response = Summary { results }.to_response();
+    response.push_str(&protocol::cursor_line(pending.cursor()));
+    response.push('\n');
+    response
+}
+
+/// Number of waiting refreshes, the bytes they hold and the budget.
+pub fn stats() -> (usize, usize, usize) {
+    let pending = PENDING.lock().unwrap();
+    (pending.len(), pending.used(), pending.budget())
+}
@@ -0,0 +1,95 @@
+use auto_refresh_common::poll::{self, Observed, Poller};
+use std::collections::HashSet;
+use std::thread;
+use std::time::Duration;
+
+use crate::{config, index, refresh_files};
+
+/// Starts polling the indexed files if the config asks for it.
+pub fn start() {
+    let interval = match config::CONFIG.poll_interval {
+        Some(interval) => interval,
+        None => return,
+    };
+    let poller = thread::Builder::new()
+        .name("auto-refresh-poll".to_string())
+        .spawn(move || poll_loop(interval));
+    match poller {
+        Ok(_) => log!(
+            Info,
+            Refresh,
+            "Polling the mod folders every {}s.",
+            interval.as_secs()
+        ),
+        Err(err) => log!(Error, Refresh, "Failed to spawn the poll thread: {:?}", err),
+    }
+}
+
+fn poll_loop(interval: Duration) {
+    let mut poller = Poller::new();
+    loop {
+        thread::sleep(interval);
+        let changed = changed_paths(&mut poller);
+        if changed.is_empty() {
+            continue;
+        }
+        log!(
+            Info,
+            Refresh,
+            "Polling found {} changed files.",
+            changed.len()
+        );
+        let summary = refresh_files(&changed);
+        for result in &summary.results {
+            log!(Debug, Refresh, "{}", result);
+        }
+        log!(Info, Refresh, "Polling: {}.", summary);
+    }
+}
+
+/// Stats every indexed file that isn't excluded. Files added since the last scan only show up
+/// after a `RESCAN`.
+fn changed_paths(poller: &mut Poller) -> Vec<String> {
+    let exclusions = &config::CONFIG.poll_exclusions;
+    // Stat without holding the index, refreshes need it
+    let entries: Vec<_> = index::FILES_INFO
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(arc_path, _)| !poll::is_excluded(exclusions, arc_path))
+        .map(|(arc_path, entry)| {
+            (
+                arc_path.clone(),
+                entry.physical_path(arc_path),
+                entry.stamp(),
+            )
+        })
+        .collect();
+    let indexed_paths: HashSet<&str> = entries.iter().map(|(path, _, _)| path.as_str()).collect();
+    poller.retain(|arc_path| indexed_paths.contains(arc_path));
+
+    let mut changed = vec![];
+    for (arc_path, physical_path, indexed) in entries {
+        // Deleted files drop out of the index on the next scan
+        let current = match index::current_stamp(&physical_path) {
+            Some(current) => current,
+            None => continue,
+        };
+        if poller.observe(&arc_path, indexed, current) == Observed::Changed {
+            changed.push(arc_path);
+        }
+    }
+    changed
+}
+
+/// For `STATUS`.
+pub fn describe() -> String {
+    match config::CONFIG.poll_interval {
+        Some(interval) => format!(
+            "every {}s, {} exclusions",
+            interval.as_secs(),
+            config::CONFIG.poll_exclusions.len()
+        ),
+        None => "off".to_string(),
+    }
+}
@@ -0,0 +1,106 @@
+use once_cell::sync::Lazy;
+use skyline::nn;
+use std::fmt;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::{offsets, resource, RefreshError};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Writes into game memory are funneled through this queue. A single worker applies them one
+/// at a time while holding `FilesystemInfo::mutex`, so they can't race the loader or inflate
+/// threads loading or freeing the same buffers.
+/// `None` if the worker couldn't be started.
+static QUEUE: Lazy<Option<Mutex<Sender<Job>>>> = Lazy::new(|| {
+    let (sender, receiver) = channel::<Job>();
+    let spawned = thread::Builder::new()
+        .name("auto-refresh-apply".to_string())
+        .spawn(move || {
+            for job in receiver {
+                let _lock = FilesystemLock::acquire();
+                job();
+            }
+        });
+    match spawned {
+        Ok(_) => Some(Mutex::new(sender)),
+        Err(err) => {
+            log!(
+                Error,
+                Refresh,
+                "Failed to spawn the refresh worker: {}",
+                err
+            );
+            None
+        }
+    }
+});
+
+/// The worker couldn't be started or stopped taking jobs, nothing can be written anymore.
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerStopped;
+
+impl fmt::Display for WorkerStopped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the refresh worker has stopped")
+    }
+}
+
+impl From<WorkerStopped> for RefreshError {
+    fn from(err: WorkerStopped) -> Self {
+        RefreshError::Failed(err.to_string())
+    }
+}
+
+struct FilesystemLock(Option<*mut nn::os::MutexType>);
+
+impl FilesystemLock {
+    fn acquire() -> Self {
+        if !offsets::is_supported() || !resource::initialized() {
+            return Self(None);
+        }
+        let mutex = resource::filesystem_info().mutex;
+        if mutex.is_null() {
+            return Self(None);
+        }
+        unsafe { nn::os::LockMutex(mutex) };
+        Self(Some(mutex))
+    }
+}
+
+impl Drop for FilesystemLock {
+    fn drop(&mut self) {
+        if let Some(mutex) = self.0 {
+            unsafe { nn::os::UnlockMutex(mutex) };
+        }
+    }
+}
+
+/// Queues `job` and blocks until the worker has applied it, returning its result.
+pub fn apply<R, F>(job: F) -> Result<R, WorkerStopped>
+where
+    R: Send + 'static,
+    F: FnOnce() -> R + Send + 'static,
+{
+    let (sender, receiver) = channel();
+    QUEUE
+        .as_ref()
+        .ok_or(WorkerStopped)?
+        .lock()
+        .map_err(|_| WorkerStopped)?
+        .send(Box::new(move || {
+            let _ = sender.send(job());
+        }))
+        .map_err(|_| WorkerStopped)?;
+    receiver.recv().map_err(|_| WorkerStopped)
+}
+
+/// `apply` for jobs that can fail, with a stopped worker as one more failure.
+pub fn try_apply<T, F>(job: F) -> Result<T, RefreshError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, RefreshError> + Send + 'static,
+{
+    apply(job).unwrap_or_else(|err| Err(err.into()))
+}
@@ -2,7 +2,7 @@ mod containers;
 mod types;
 
 pub use containers::*;
-use smash_arc::{LoadedArc, LoadedSearchSection};
+use smash_arc::{LoadedArc, LoadedSearchSection, Region};
 pub use types::*;
 
 use crate::offsets;
@@ -13,13 +13,19 @@ fn offset_to_addr<T>(offset: usize) -> *mut T {
     }
 }
 
+/// Only call the accessors below once `offsets::is_supported()` returned true. Every FFI export
+/// that reaches them checks it first, so this can't panic across the FFI boundary.
+fn required(offset: Option<usize>) -> usize {
+    offset.expect("[auto-refresh] Offset is missing for this game build")
+}
+
 pub fn filesystem_info() -> &'static FilesystemInfo {
-    let addr = offset_to_addr::<&'static FilesystemInfo>(offsets::filesystem_info());
+    let addr = offset_to_addr::<&'static FilesystemInfo>(required(offsets::filesystem_info()));
     unsafe { *addr }
 }
 
 pub fn filesystem_info_mut() -> &'static mut FilesystemInfo {
-    let addr = offset_to_addr::<&'static mut FilesystemInfo>(offsets::filesystem_info());
+    let addr = offset_to_addr::<&'static mut FilesystemInfo>(required(offsets::filesystem_info()));
     unsafe { *addr }
 }
 
@@ -40,15 +46,25 @@ pub fn search_mut() -> &'static mut LoadedSearchSection {
 }
 
 pub fn res_service() -> &'static ResServiceNX {
-    let addr = offset_to_addr::<&'static ResServiceNX>(offsets::res_service());
+    let addr = offset_to_addr::<&'static ResServiceNX>(required(offsets::res_service()));
     unsafe { *addr }
 }
 
 pub fn res_service_mut() -> &'static mut ResServiceNX {
-    let addr = offset_to_addr::<&'static mut ResServiceNX>(offsets::res_service());
+    let addr = offset_to_addr::<&'static mut ResServiceNX>(required(offsets::res_service()));
     unsafe { *addr }
 }
 
+/// The region regional files are loaded for, following the game's language.
+pub fn region() -> Region {
+    res_service().get_region()
+}
+
+/// Whether the game has set the filesystem info pointer yet. The static holding it always
+/// exists, so it's the stored pointer that gets checked.
 pub fn initialized() -> bool {
-    !offset_to_addr::<&'static FilesystemInfo>(offsets::filesystem_info()).is_null()
+    match offsets::filesystem_info() {
+        Some(offset) => unsafe { !(*offset_to_addr::<*const FilesystemInfo>(offset)).is_null() },
+        None => false,
+    }
 }
@@ -0,0 +1,106 @@
+use auto_refresh_common::arc_path;
+use auto_refresh_common::protocol::{FileResult, Summary};
+use auto_refresh_common::snapshot::{Recorded, Snapshot, SnapshotStore};
+use once_cell::sync::Lazy;
+use smash_arc::Hash40;
+use std::sync::Mutex;
+
+use crate::{bntx, config, events, loaded_buffer, queue};
+
+static SNAPSHOTS: Lazy<Mutex<SnapshotStore>> =
+    Lazy::new(|| Mutex::new(SnapshotStore::new(config::CONFIG.snapshot_budget)));
+
+/// Keeps a copy of `buffer` if this is the first time `hash` gets overwritten.
+pub fn record(hash: u64, path: &str, buffer: &[u8]) {
+    let mut snapshots = SNAPSHOTS.lock().unwrap();
+    match snapshots.record(hash, path, buffer.as_ptr() as u64, buffer) {
+        Recorded::New => log!(
+            Info,
+            Refresh,
+            "Snapshotted {} ({:#x} bytes).",
+            path,
+            buffer.len()
+        ),
+        Recorded::Existing => {}
+        Recorded::OverBudget => log!(
+            Warn,
+            Refresh,
+            "Snapshot budget of {:#x} bytes is used up, {} can't be restored.",
+            snapshots.budget(),
+            path
+        ),
+    }
+}
+
+/// Writes the snapshot back into the loaded file. Only call this from a `queue` job.
+fn write_back(hash: u64, original: &Snapshot) -> FileResult {
+    let path = original.path.as_str();
+    if path.ends_with("bntx") {
+        return match bntx::restore(Hash40::from(hash), original) {
+            Ok(report) if report.rejected.is_empty() => FileResult::refreshed(path),
+            Ok(report) => FileResult::skipped(path, report.describe_rejected()),
+            Err(err) => FileResult::skipped(path, err.to_string()),
+        };
+    }
+
+    match loaded_buffer(Hash40::from(hash)) {
+        Ok(buffer) if buffer.len() == original.data.len() => {
+            buffer.copy_from_slice(&original.data);
+            FileResult::refreshed(path)
+        }
+        Ok(buffer) => FileResult::skipped(
+            path,
+            format!(
+                "loaded size {:#x} does not match snapshot size {:#x}",
+                buffer.len(),
+                original.data.len()
+            ),
+        ),
+        Err(err) => FileResult::skipped(path, err.to_string()),
+    }
+}
+
+/// Restores `hash`, dropping its snapshot once it has been written back.
+fn restore_hash(hash: u64) -> Option<FileResult> {
+    let original = SNAPSHOTS.lock().unwrap().get(hash)?.clone();
+    let path = original.path.clone();
+    let result = queue::apply(move || write_back(hash, &original))
+        .unwrap_or_else(|err| FileResult::skipped(path, err.to_string()));
+    events::notify(hash, &result);
+    if result.is_refreshed() {
+        log!(Info, Refresh, "Restored {}.", result.path);
+        SNAPSHOTS.lock().unwrap().remove(hash);
+    }
+    Some(result)
+}
+
+pub fn restore(paths: &[String]) -> Summary {
+    Summary {
+        results: paths
+            .iter()
+            .map(|path| match arc_path::normalize(path) {
+                Ok(path) => restore_hash(smash_arc::hash40(&path).as_u64())
+                    .unwrap_or_else(|| FileResult::skipped(path.as_str(), "no snapshot")),
+                Err(err) => FileResult::skipped(path.as_str(), err.to_string()),
+            })
+            .collect(),
+    }
+}
+
+pub fn restore_all() -> Summary {
+    let hashes = SNAPSHOTS.lock().unwrap().hashes();
+    Summary {
+        results: hashes.into_iter().filter_map(restore_hash).collect(),
+    }
+}
+
+/// `None` if the snapshot table can't be read.
+pub fn has_snapshot(hash: u64) -> Option<bool> {
+    Some(SNAPSHOTS.lock().ok()?.get(hash).is_some())
+}
+
+/// Number of snapshots, the bytes they hold and the budget.
+pub fn stats() -> (usize, usize, usize) {
+    let snapshots = SNAPSHOTS.lock().unwrap();
+    (snapshots.len(), snapshots.used(), snapshots.budget())
+}
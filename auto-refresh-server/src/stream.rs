@@ -0,0 +1,67 @@
+use auto_refresh_common::protocol::FileResult;
+use smash_arc::{ArcLookup, Hash40};
+
+use crate::{offsets, resource};
+
+/// Size the ARC's stream tables give `hash`. Stream files are read straight from the ARC
+/// whenever the game opens them, so they never show up in `LoadedData`.
+fn stream_size(hash: Hash40) -> Result<u64, String> {
+    if !offsets::is_supported() {
+        return Err("unsupported game version".to_string());
+    }
+    if !resource::initialized() {
+        return Err("the filesystem isn't initialized yet".to_string());
+    }
+    let arc = resource::arc();
+    let entry = arc
+        .get_stream_entries()
+        .iter()
+        .find(|entry| entry.hash.hash40() == hash)
+        .ok_or_else(|| "not found in the arc's stream tables".to_string())?;
+    // Localized streams have one data entry per locale after this one, they're all sized
+    // alike so the first one is enough here
+    let data_index = *arc
+        .get_stream_file_indices()
+        .get(entry.hash.index() as usize)
+        .ok_or_else(|| "stream entry points past the stream file indices".to_string())?;
+    arc.get_stream_datas()
+        .get(data_index as usize)
+        .map(|data| data.size)
+        .ok_or_else(|| "stream file index points past the stream datas".to_string())
+}
+
+/// Checks a stream file against the ARC. It can't be refreshed in place, but ARCropolis reads
+/// the new one from the SD card the next time the game opens it.
+pub fn refresh(path: &str, hash: Hash40, size: u64) -> FileResult {
+    let original_size = match stream_size(hash) {
+        Ok(original_size) => original_size,
+        Err(reason) => {
+            log!(Warn, Refresh, "Skipped stream file {}: {}.", path, reason);
+            return FileResult::skipped(path, reason);
+        }
+    };
+    if size > original_size {
+        log!(
+            Warn,
+            Refresh,
+            "{} grew from {:#x} to {:#x} bytes, it needs a restart.",
+            path,
+            original_size,
+            size
+        );
+        return FileResult::skipped(
+            path,
+            format!(
+                "larger than the {:#x} bytes in the arc, restart the game",
+                original_size
+            ),
+        );
+    }
+    log!(
+        Info,
+        Refresh,
+        "{} is a stream file, re-open it to use it.",
+        path
+    );
+    FileResult::reopen(path, "stream file, used the next time the game opens it")
+}
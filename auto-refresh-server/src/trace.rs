@@ -0,0 +1,158 @@
+use auto_refresh_common::trace::{TraceEntry, TraceSource, MAX_ENTRIES};
+use once_cell::sync::Lazy;
+use skyline::nn;
+use smash_arc::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::resource::{self, ListNode, LoadType};
+use crate::{bntx, index, offsets};
+
+/// How often the res_lists are sampled while tracing. Requests that come and go between two
+/// samples are only seen by the threaded load hook.
+const RES_LIST_POLL_INTERVAL: Duration = Duration::from_millis(2);
+const IDLE_INTERVAL: Duration = Duration::from_millis(100);
+
+static TRACING: AtomicBool = AtomicBool::new(false);
+static START_POLLER: Once = Once::new();
+
+static TRACE: Lazy<Mutex<Option<Recording>>> = Lazy::new(|| Mutex::new(None));
+
+struct Recording {
+    started: Instant,
+    entries: Vec<TraceEntry>,
+    /// Indexed mod files, so their entries can carry a path
+    paths: HashMap<u64, String>,
+    dropped: usize,
+}
+
+impl Recording {
+    fn push(&mut self, hash: u64, size: usize, source: TraceSource) {
+        if self.entries.len() >= MAX_ENTRIES {
+            self.dropped += 1;
+            return;
+        }
+        self.entries.push(TraceEntry {
+            time_ms: self.started.elapsed().as_millis() as u64,
+            hash,
+            size,
+            source,
+            path: self.paths.get(&hash).cloned(),
+        });
+    }
+}
+
+pub fn is_tracing() -> bool {
+    TRACING.load(Ordering::SeqCst)
+}
+
+/// Starts a new trace, discarding the current one.
+pub fn start() -> String {
+    let paths = index::FILES_INFO.read().unwrap().paths_by_hash();
+    *TRACE.lock().unwrap() = Some(Recording {
+        started: Instant::now(),
+        entries: vec![],
+        paths,
+        dropped: 0,
+    });
+
+    TRACING.store(true, Ordering::SeqCst);
+    if offsets::is_supported() {
+        START_POLLER.call_once(|| {
+            thread::spawn(poll_res_lists);
+        });
+    }
+
+    log!(Info, Refresh, "Load trace started.");
+    if bntx::is_enabled() {
+        "Trace started\n".to_string()
+    } else {
+        "Trace started, without the threaded load hook only res_list requests are seen\n"
+            .to_string()
+    }
+}
+
+pub fn stop() -> Vec<TraceEntry> {
+    TRACING.store(false, Ordering::SeqCst);
+    match TRACE.lock().unwrap().take() {
+        Some(recording) => {
+            log!(
+                Info,
+                Refresh,
+                "Load trace stopped, {} entries.",
+                recording.entries.len()
+            );
+            if recording.dropped > 0 {
+                log!(
+                    Warn,
+                    Refresh,
+                    "{} entries past the first {} were dropped.",
+                    recording.dropped,
+                    MAX_ENTRIES
+                );
+            }
+            recording.entries
+        }
+        None => vec![],
+    }
+}
+
+/// Called from the threaded load hook.
+pub fn on_threaded_load(hash: u64, size: usize) {
+    if !is_tracing() {
+        return;
+    }
+    if let Some(recording) = TRACE.lock().unwrap().as_mut() {
+        recording.push(hash, size, TraceSource::ThreadedLoad);
+    }
+}
+
+/// File requests currently queued, keyed by list node so a request stays the same entry for
+/// as long as it sits in the queue.
+fn queued_requests() -> HashSet<(usize, u32)> {
+    let mut requests = HashSet::new();
+    let res_service = resource::res_service();
+    unsafe { nn::os::LockMutex(res_service.mutex) };
+    for list in res_service.res_lists.iter() {
+        for node in list.node_iter() {
+            if let LoadType::File = node.data.ty {
+                requests.insert((node as *const ListNode as usize, node.data.filepath_index));
+            }
+        }
+    }
+    unsafe { nn::os::UnlockMutex(res_service.mutex) };
+    requests
+}
+
+fn poll_res_lists() {
+    let mut seen = HashSet::new();
+    loop {
+        if !is_tracing() || !resource::initialized() {
+            seen.clear();
+            thread::sleep(IDLE_INTERVAL);
+            continue;
+        }
+        let queued = queued_requests();
+        let arc = resource::arc();
+        let region = resource::region();
+        for (_, filepath_index) in queued.difference(&seen) {
+            let file_path = match arc.get_file_paths().get(*filepath_index as usize) {
+                Some(file_path) => file_path,
+                None => continue,
+            };
+            let hash = file_path.path.hash40();
+            let size = arc
+                .get_file_info_from_hash(hash)
+                .map(|file_info| arc.get_file_data(file_info, region).decomp_size as usize)
+                .unwrap_or(0);
+            if let Some(recording) = TRACE.lock().unwrap().as_mut() {
+                recording.push(hash.as_u64(), size, TraceSource::ResList);
+            }
+        }
+        seen = queued;
+        thread::sleep(RES_LIST_POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,50 @@
+use once_cell::sync::Lazy;
+use skyline::nn;
+
+use crate::offsets::{FREE_FILES_THREADED, LOAD_FILES_THREADED};
+
+static GAME_VERSION: Lazy<String> = Lazy::new(read_display_version);
+
+/// Offsets confirmed by hand for one game version, keyed by the symbol names in `offsets`.
+pub struct KnownVersion {
+    pub version: &'static str,
+    pub offsets: &'static [(&'static str, usize)],
+}
+
+impl KnownVersion {
+    pub fn offset(&self, name: &str) -> Option<usize> {
+        self.offsets
+            .iter()
+            .find(|(symbol, _)| *symbol == name)
+            .map(|(_, offset)| *offset)
+    }
+}
+
+/// A version only reports its offsets as verified once it lists every symbol in `offsets`,
+/// and the scan agrees with all of them.
+static KNOWN_VERSIONS: &[KnownVersion] = &[KnownVersion {
+    version: "13.0.1",
+    offsets: &[(LOAD_FILES_THREADED, 0x37a17ac), (FREE_FILES_THREADED, 0x37a1470)],
+}];
+
+fn read_display_version() -> String {
+    let mut display_version = nn::oe::DisplayVersion { name: [0; 16] };
+    unsafe { nn::oe::GetDisplayVersion(&mut display_version) };
+    display_version
+        .name
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8 as char)
+        .collect()
+}
+
+/// The running game's display version, e.g. `13.0.1`.
+pub fn game_version() -> &'static str {
+    &GAME_VERSION
+}
+
+pub fn known_version() -> Option<&'static KnownVersion> {
+    KNOWN_VERSIONS
+        .iter()
+        .find(|known| known.version == game_version())
+}